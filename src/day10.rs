@@ -1,4 +1,7 @@
-use anyhow::Result;
+use std::{collections::HashSet, fmt::Display, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
 
 pub fn run() -> Result<()> {
     let content = std::fs::read_to_string("inputs/day10.txt")?;
@@ -24,7 +27,7 @@ pub fn run() -> Result<()> {
                 .into_incomplete()
                 .map(|s| checker.incomplete_score(&s))
         })
-        .collect();
+        .collect::<Result<_>>()?;
     scores.sort_unstable();
     let n = scores.len();
     let middle_score = scores[n / 2];
@@ -33,24 +36,303 @@ pub fn run() -> Result<()> {
     Ok(())
 }
 
+/// Report the errors in every line of a file (the puzzle input by default), with the chunks of the
+/// puzzle or those of a `chunks=FILE` table. With `--fix`, also suggest the cheapest repair.
+pub fn check(args: &[String]) -> Result<()> {
+    let mut path = "inputs/day10.txt";
+    let mut checker = SyntaxChecker::new();
+    let mut fix = false;
+    for arg in args {
+        if arg == "--fix" {
+            fix = true;
+            continue;
+        }
+        match arg.split_once('=') {
+            Some(("chunks", table)) => {
+                let table = std::fs::read_to_string(table)
+                    .with_context(|| format!("Cannot read {}", table))?;
+                checker = SyntaxChecker::from_str(&table)?;
+            }
+            Some((name, _)) => bail!("Unknown setting {}", name),
+            None => path = arg,
+        }
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    for (line, number) in content.lines().zip(1..) {
+        let status = checker.line_status(line);
+        if let Some(diagnostic) = status.diagnostic(line, number) {
+            let score = match status {
+                LineStatus::Incomplete(s) => checker.incomplete_score(&s),
+                status => Ok(status
+                    .into_corrupted()
+                    .map_or(0, |c| checker.corruption_score(c))),
+            };
+            match score {
+                Ok(score) => println!("{}  = score: {}", diagnostic, score),
+                Err(e) => println!("{}  = note: {}", diagnostic, e),
+            }
+            if fix {
+                match checker.repair(line) {
+                    Ok(repair) => println!(
+                        "  = help: {} edits: {}\n  = help: {}",
+                        repair.cost(),
+                        repair.edits.iter().join(", "),
+                        repair.line
+                    ),
+                    Err(e) => println!("  = note: {}", e),
+                }
+            }
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum LineStatus {
-    Corrupted(char),
+    /// A chunk was closed with the wrong character. Columns start at 0.
+    Corrupted {
+        column: usize,
+        found: char,
+        expected: char,
+        opened_at: usize,
+    },
+    /// A closing character was found while no chunk was open.
+    UnexpectedCloser {
+        column: usize,
+        found: char,
+    },
     Incomplete(String),
 }
 
 impl LineStatus {
+    /// The illegal character of a corrupted line, including unexpected closing characters.
     fn into_corrupted(self) -> Option<char> {
         match self {
-            LineStatus::Corrupted(c) => Some(c),
+            LineStatus::Corrupted { found, .. } | LineStatus::UnexpectedCloser { found, .. } => {
+                Some(found)
+            }
             LineStatus::Incomplete(_) => None,
         }
     }
 
     fn into_incomplete(self) -> Option<String> {
         match self {
-            LineStatus::Corrupted(_) => None,
             LineStatus::Incomplete(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Describe what's wrong with `line`, line `number` of its file, the way a compiler would,
+    /// with a caret under the offending column. Returns `None` if the line is fine.
+    fn diagnostic(&self, line: &str, number: usize) -> Option<String> {
+        let (message, column, label, opener) = match self {
+            LineStatus::Corrupted {
+                column,
+                found,
+                expected,
+                opened_at,
+            } => (
+                format!("expected `{}`, found `{}`", expected, found),
+                *column,
+                format!("expected `{}`", expected),
+                Some(*opened_at),
+            ),
+            LineStatus::UnexpectedCloser { column, found } => (
+                format!("unexpected closing `{}`", found),
+                *column,
+                "no open chunk to close".to_string(),
+                None,
+            ),
+            LineStatus::Incomplete(s) if s.is_empty() => return None,
+            LineStatus::Incomplete(s) => (
+                "unclosed chunks".to_string(),
+                line.chars().count(),
+                format!("expected `{}`", s),
+                None,
+            ),
+        };
+
+        let pad = " ".repeat(number.to_string().len());
+        let mut out = format!(
+            "error: {}\n{}--> line {}, column {}\n{} |\n{} | {}\n",
+            message,
+            pad,
+            number,
+            column + 1,
+            pad,
+            number,
+            line
+        );
+        match opener {
+            Some(opener) => {
+                let opening = line.chars().nth(opener).unwrap_or(' ');
+                let indent = " ".repeat(opener);
+                out += &format!(
+                    "{} | {}-{}^ {}\n",
+                    pad,
+                    indent,
+                    " ".repeat(column - opener - 1),
+                    label
+                );
+                out += &format!("{} | {}|\n", pad, indent);
+                out += &format!("{} | {}`{}` opened here\n", pad, indent, opening);
+            }
+            None => out += &format!("{} | {}^ {}\n", pad, " ".repeat(column), label),
+        }
+
+        Some(out)
+    }
+}
+
+/// A change to a line. Columns are those of the original line, starting at 0.
+#[derive(Debug, PartialEq, Eq)]
+enum Edit {
+    /// Insert a character before the given column.
+    Insert {
+        column: usize,
+        c: char,
+    },
+    Delete {
+        column: usize,
+        c: char,
+    },
+    Replace {
+        column: usize,
+        from: char,
+        to: char,
+    },
+}
+
+impl Edit {
+    fn column(&self) -> usize {
+        match self {
+            Edit::Insert { column, .. }
+            | Edit::Delete { column, .. }
+            | Edit::Replace { column, .. } => *column,
+        }
+    }
+}
+
+impl Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edit::Insert { column, c } => write!(f, "insert `{}` at column {}", c, column + 1),
+            Edit::Delete { column, c } => write!(f, "delete `{}` at column {}", c, column + 1),
+            Edit::Replace { column, from, to } => {
+                write!(
+                    f,
+                    "replace `{}` with `{}` at column {}",
+                    from,
+                    to,
+                    column + 1
+                )
+            }
+        }
+    }
+}
+
+/// A line fixed so that all its chunks are closed, and the edits made to get there.
+#[derive(Debug, PartialEq, Eq)]
+struct Repair {
+    line: String,
+    edits: Vec<Edit>,
+}
+
+impl Repair {
+    fn cost(&self) -> usize {
+        self.edits.len()
+    }
+}
+
+/// The longest lines `SyntaxChecker::repair` accepts, in delimiters. Repairing a line takes cubic
+/// time and quadratic memory in its number of delimiters, and rebuilding the repaired line recurses
+/// once per delimiter.
+const MAX_REPAIR_DELIMITERS: usize = 500;
+
+/// How the first delimiter of a part of a line is handled by a repair.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    /// Nothing to repair.
+    Empty,
+    /// The delimiter forms a chunk with a later one, after replacing them if needed.
+    Pair(usize),
+    /// The delimiter opens a chunk, closed by inserting a character at the end.
+    Close,
+    /// The delimiter is removed.
+    Delete,
+}
+
+/// The cheapest steps to repair each part of a line, as found by `SyntaxChecker::repair`.
+struct RepairPlan<'a> {
+    checker: &'a SyntaxChecker,
+    chars: Vec<char>,
+    /// Columns of the delimiters.
+    delimiters: Vec<usize>,
+    /// `steps[i][j]` repairs the delimiters `i..j`.
+    steps: Vec<Vec<Step>>,
+}
+
+impl<'a> RepairPlan<'a> {
+    /// Column of the `i`th delimiter, or the end of the line.
+    fn column(&self, i: usize) -> usize {
+        self.delimiters.get(i).copied().unwrap_or(self.chars.len())
+    }
+
+    /// Write the `i`th delimiter as `c`, followed by the text up to the next delimiter.
+    fn emit(&self, i: usize, c: char, out: &mut String, edits: &mut Vec<Edit>) {
+        let column = self.delimiters[i];
+        if c != self.chars[column] {
+            edits.push(Edit::Replace {
+                column,
+                from: self.chars[column],
+                to: c,
+            });
+            out.push(c);
+        }
+        let end = self.column(i + 1);
+        if c == self.chars[column] {
+            out.extend(&self.chars[column..end]);
+        } else {
+            out.extend(&self.chars[column + 1..end]);
+        }
+    }
+
+    /// Write the repaired delimiters `i..j`, and the text after them.
+    fn rebuild(&self, i: usize, j: usize, out: &mut String, edits: &mut Vec<Edit>) {
+        if i == j {
+            return;
+        }
+        let c = self.chars[self.delimiters[i]];
+        match self.steps[i][j] {
+            Step::Empty => {}
+            Step::Pair(k) => {
+                let (opening, closing) = self.checker.pair(c, self.chars[self.delimiters[k]]);
+                self.emit(i, opening, out, edits);
+                self.rebuild(i + 1, k, out, edits);
+                self.emit(k, closing, out, edits);
+                self.rebuild(k + 1, j, out, edits);
+            }
+            Step::Close => {
+                self.emit(i, c, out, edits);
+                self.rebuild(i + 1, j, out, edits);
+                let closing = self.checker.matching_char(c);
+                edits.push(Edit::Insert {
+                    column: self.column(j),
+                    c: closing,
+                });
+                out.push(closing);
+            }
+            Step::Delete => {
+                edits.push(Edit::Delete {
+                    column: self.delimiters[i],
+                    c,
+                });
+                out.extend(&self.chars[self.delimiters[i] + 1..self.column(i + 1)]);
+                self.rebuild(i + 1, j, out, edits);
+            }
         }
     }
 }
@@ -93,11 +375,41 @@ impl Chunk {
     }
 }
 
+impl FromStr for Chunk {
+    type Err = anyhow::Error;
+
+    /// Parse a chunk described as `OPENING CLOSING CORRUPT_SCORE INCOMPLETE_SCORE`, e.g.
+    /// `( ) 3 1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [opening, closing, corrupt_score, incomplete_score] = fields[..] else {
+            bail!("Expected 4 fields in chunk {:?}", s);
+        };
+        let delimiter = |field: &str| -> Result<char> {
+            let mut chars = field.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => bail!("Delimiter {:?} should be a single character", field),
+            }
+        };
+
+        Ok(Chunk::new(
+            delimiter(opening)?,
+            delimiter(closing)?,
+            corrupt_score.parse().context("Invalid corrupt score")?,
+            incomplete_score
+                .parse()
+                .context("Invalid incomplete score")?,
+        ))
+    }
+}
+
 struct SyntaxChecker {
     syntax: Box<[Chunk]>,
 }
 
 impl SyntaxChecker {
+    /// The checker for the chunks of the puzzle.
     pub fn new() -> Self {
         let syntax = vec![
             Chunk::new('(', ')', 3, 1),
@@ -105,30 +417,126 @@ impl SyntaxChecker {
             Chunk::new('{', '}', 1197, 3),
             Chunk::new('<', '>', 25137, 4),
         ];
-        Self {
-            syntax: syntax.into_boxed_slice(),
+        Self::with_chunks(syntax).expect("invalid default syntax")
+    }
+
+    /// A checker for any set of chunks. Each character can only be used once, as either the
+    /// opening or the closing of a chunk.
+    fn with_chunks(syntax: Vec<Chunk>) -> Result<Self> {
+        ensure!(!syntax.is_empty(), "No chunks");
+        let mut delimiters = HashSet::new();
+        for chunk in &syntax {
+            for c in [chunk.opening(), chunk.closing()] {
+                ensure!(delimiters.insert(c), "{:?} is used by several chunks", c);
+            }
         }
+
+        Ok(Self {
+            syntax: syntax.into_boxed_slice(),
+        })
     }
 
     fn line_status(&self, line: &str) -> LineStatus {
+        // open chunks, with the column they were opened at
         let mut stack = vec![];
 
-        for c in line.chars() {
+        for (column, c) in line.chars().enumerate() {
             if self.is_opening(c) {
-                stack.push(c);
+                stack.push((c, column));
             } else if self.is_closing(c) {
-                let matching = stack.pop().expect("invalid line");
+                let Some((matching, opened_at)) = stack.pop() else {
+                    return LineStatus::UnexpectedCloser { column, found: c };
+                };
                 if !self.is_pair(matching, c) {
-                    return LineStatus::Corrupted(c);
+                    return LineStatus::Corrupted {
+                        column,
+                        found: c,
+                        expected: self.matching_char(matching),
+                        opened_at,
+                    };
                 }
             }
         }
 
         LineStatus::Incomplete(String::from_iter(
-            stack.into_iter().rev().map(|c| self.matching_char(c)),
+            stack.into_iter().rev().map(|(c, _)| self.matching_char(c)),
         ))
     }
 
+    /// Repair `line` with as few edits as possible, each edit inserting, deleting or replacing a
+    /// single delimiter. Other characters are left alone.
+    ///
+    /// This isn't limited to completing the line: for incomplete lines, replacing an opening
+    /// character can be cheaper than adding all the missing closing characters.
+    ///
+    /// Fails if the line has more than `MAX_REPAIR_DELIMITERS` delimiters.
+    fn repair(&self, line: &str) -> Result<Repair> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let delimiters = (0..chars.len())
+            .filter(|i| self.is_opening(chars[*i]) || self.is_closing(chars[*i]))
+            .collect::<Vec<_>>();
+        let n = delimiters.len();
+        ensure!(
+            n <= MAX_REPAIR_DELIMITERS,
+            "Can't repair a line with more than {} delimiters ({} here)",
+            MAX_REPAIR_DELIMITERS,
+            n
+        );
+
+        // Fewest edits to repair the delimiters `i..j`: the first one either forms a chunk with
+        // a later one, or it is closed by an insertion (for opening characters) or deleted
+        // (for closing characters), which both cost one edit.
+        let mut cost = vec![vec![0; n + 1]; n + 1];
+        let mut steps = vec![vec![Step::Empty; n + 1]; n + 1];
+        for len in 1..=n {
+            for i in 0..=n - len {
+                let j = i + len;
+                let a = chars[delimiters[i]];
+                let mut best = if self.is_opening(a) {
+                    (1 + cost[i + 1][j], Step::Close)
+                } else {
+                    (1 + cost[i + 1][j], Step::Delete)
+                };
+                for k in i + 1..j {
+                    let b = chars[delimiters[k]];
+                    let (opening, closing) = self.pair(a, b);
+                    let replacements = (opening != a) as usize + (closing != b) as usize;
+                    let total = replacements + cost[i + 1][k] + cost[k + 1][j];
+                    if total < best.0 {
+                        best = (total, Step::Pair(k));
+                    }
+                }
+                (cost[i][j], steps[i][j]) = best;
+            }
+        }
+
+        let plan = RepairPlan {
+            checker: self,
+            chars,
+            delimiters,
+            steps,
+        };
+        let mut out = String::from_iter(&plan.chars[..plan.column(0)]);
+        let mut edits = vec![];
+        plan.rebuild(0, n, &mut out, &mut edits);
+        edits.sort_by_key(Edit::column);
+
+        Ok(Repair { line: out, edits })
+    }
+
+    /// The characters `a` and `b` should be replaced with to form a chunk, changing as few of
+    /// them as possible.
+    fn pair(&self, a: char, b: char) -> (char, char) {
+        match (
+            self.get_chunk_from_opening(a),
+            self.get_chunk_from_closing(b),
+        ) {
+            (Some(chunk), _) => (a, chunk.closing()),
+            (None, Some(chunk)) => (chunk.opening(), b),
+            (None, None) => (self.syntax[0].opening(), self.syntax[0].closing()),
+        }
+    }
+
     fn is_opening(&self, c: char) -> bool {
         self.get_chunk_from_opening(c).is_some()
     }
@@ -155,14 +563,26 @@ impl SyntaxChecker {
             .unwrap_or(0)
     }
 
-    fn incomplete_score(&self, s: &str) -> u64 {
-        s.chars().fold(0u64, |score, c| {
-            let char_score = self
-                .get_chunk_from_closing(c)
-                .map(Chunk::incomplete_score)
-                .unwrap_or(0);
-            score * 5 + char_score
-        })
+    /// Score of a completion string: each character is a digit whose base is one more than the
+    /// highest incomplete score (5 for the puzzle), so that different strings get different
+    /// scores. Fails if the score doesn't fit in 64 bits.
+    fn incomplete_score(&self, s: &str) -> Result<u64> {
+        let base = self
+            .syntax
+            .iter()
+            .map(Chunk::incomplete_score)
+            .max()
+            .unwrap_or(0)
+            .checked_add(1);
+        s.chars()
+            .try_fold(0u64, |score, c| {
+                let char_score = self
+                    .get_chunk_from_closing(c)
+                    .map(Chunk::incomplete_score)
+                    .unwrap_or(0);
+                score.checked_mul(base?)?.checked_add(char_score)
+            })
+            .with_context(|| format!("The score of {} overflows 64 bits", s))
     }
 
     fn get_chunk_from_opening(&self, c: char) -> Option<&Chunk> {
@@ -173,6 +593,24 @@ impl SyntaxChecker {
     }
 }
 
+impl FromStr for SyntaxChecker {
+    type Err = anyhow::Error;
+
+    /// Parse a chunk table, with a chunk per line. Empty lines and lines starting with `//` are
+    /// ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let syntax = s
+            .lines()
+            .zip(1..)
+            .filter(|(line, _)| !line.trim().is_empty() && !line.trim().starts_with("//"))
+            .map(|(line, number)| {
+                Chunk::from_str(line).with_context(|| format!("Invalid chunk on line {}", number))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::with_chunks(syntax)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -185,9 +623,76 @@ mod tests {
             checker.line_status("[<>({}){}[([])<>]]")
         );
         assert_eq!(
-            LineStatus::Corrupted('}'),
+            LineStatus::Corrupted {
+                column: 12,
+                found: '}',
+                expected: ']',
+                opened_at: 7
+            },
             checker.line_status("{([(<{}[<>[]}>{[]{[(<()>")
         );
+        assert_eq!(
+            LineStatus::UnexpectedCloser {
+                column: 4,
+                found: '>'
+            },
+            checker.line_status("(<>)>[")
+        );
+        assert_eq!(Some('>'), checker.line_status("(<>)>[").into_corrupted());
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let checker = SyntaxChecker::new();
+        let line = "{([(<{}[<>[]}>{[]{[(<()>";
+        assert_eq!(
+            Some(
+                "\
+error: expected `]`, found `}`
+ --> line 3, column 13
+  |
+3 | {([(<{}[<>[]}>{[]{[(<()>
+  |        -    ^ expected `]`
+  |        |
+  |        `[` opened here
+"
+                .to_string()
+            ),
+            checker.line_status(line).diagnostic(line, 3)
+        );
+
+        let line = "(<>)>[";
+        assert_eq!(
+            Some(
+                "\
+error: unexpected closing `>`
+  --> line 12, column 5
+   |
+12 | (<>)>[
+   |     ^ no open chunk to close
+"
+                .to_string()
+            ),
+            checker.line_status(line).diagnostic(line, 12)
+        );
+
+        let line = "<{([{{}}[<[[[<>{}]]]>[]]";
+        assert_eq!(
+            Some(
+                "\
+error: unclosed chunks
+ --> line 1, column 25
+  |
+1 | <{([{{}}[<[[[<>{}]]]>[]]
+  |                         ^ expected `])}>`
+"
+                .to_string()
+            ),
+            checker.line_status(line).diagnostic(line, 1)
+        );
+
+        let line = "[<>({}){}[([])<>]]";
+        assert_eq!(None, checker.line_status(line).diagnostic(line, 1));
     }
 
     #[test]
@@ -205,7 +710,207 @@ mod tests {
                 .line_status("<{([{{}}[<[[[<>{}]]]>[]]")
                 .into_incomplete()
         );
-        assert_eq!(288957, checker.incomplete_score("}}]])})]"));
-        assert_eq!(5566, checker.incomplete_score(")}>]})"));
+        assert_eq!(288957, checker.incomplete_score("}}]])})]").unwrap());
+        assert_eq!(5566, checker.incomplete_score(")}>]})").unwrap());
+
+        // the score of 28 `)` fits in 64 bits, not the score of 29
+        let status = checker.line_status(&"(".repeat(40));
+        let completion = status.into_incomplete().unwrap();
+        assert!(checker.incomplete_score(&completion[..28]).is_ok());
+        assert!(checker.incomplete_score(&completion[..29]).is_err());
+        let err = checker.incomplete_score(&completion).unwrap_err();
+        assert!(err.to_string().contains("overflows"), "{}", err);
+    }
+
+    #[test]
+    fn test_chunk_table() {
+        let checker = SyntaxChecker::from_str(
+            "// guillemets and arrows
+« » 10 1
+
+→ ← 100 7",
+        )
+        .unwrap();
+        assert_eq!(
+            LineStatus::Corrupted {
+                column: 5,
+                found: '←',
+                expected: '»',
+                opened_at: 4
+            },
+            checker.line_status("«→«»«←")
+        );
+        assert_eq!(100, checker.corruption_score('←'));
+        assert_eq!(
+            LineStatus::Incomplete("←»".to_string()),
+            checker.line_status("(«→)")
+        );
+        // characters outside of the table are ignored
+        assert_eq!(
+            LineStatus::Incomplete("".to_string()),
+            checker.line_status("(«)»")
+        );
+        // digits in base 8
+        assert_eq!(7 * 8 + 1, checker.incomplete_score("←»").unwrap());
+    }
+
+    #[test]
+    fn test_invalid_chunk_table() {
+        let invalid = [
+            "",
+            "// nothing",
+            "( ) 3",
+            "( ) 3 1 0",
+            "( ) x 1",
+            "() ) 3 1",
+            "begin end 3 1",
+            "( ) 3 1\n[ ) 5 2",
+            "( ) 3 1\n( ] 5 2",
+            "( ) 3 1\n) ( 5 2",
+            "| | 3 1",
+        ];
+        for table in invalid {
+            assert!(SyntaxChecker::from_str(table).is_err(), "{:?}", table);
+        }
+    }
+
+    #[test]
+    fn test_repair() {
+        let checker = SyntaxChecker::new();
+        let repair = |line| {
+            let repair = checker.repair(line).unwrap();
+            (
+                repair.line,
+                repair.edits.iter().map(|e| e.to_string()).collect_vec(),
+            )
+        };
+
+        assert_eq!(("[<>]".to_string(), vec![]), repair("[<>]"));
+        assert_eq!(
+            (
+                "a(b)c".to_string(),
+                vec!["replace `]` with `)` at column 4".to_string()]
+            ),
+            repair("a(b]c")
+        );
+        assert_eq!(
+            (
+                "(x)".to_string(),
+                vec!["delete `)` at column 4".to_string()]
+            ),
+            repair("(x))")
+        );
+        assert_eq!(
+            (
+                "{()}".to_string(),
+                vec!["insert `}` at column 4".to_string()]
+            ),
+            repair("{()")
+        );
+        assert_eq!(
+            (
+                "()".to_string(),
+                vec![
+                    "delete `)` at column 1".to_string(),
+                    "insert `)` at column 3".to_string()
+                ]
+            ),
+            repair(")(")
+        );
+
+        // corrupted lines from the example
+        for line in [
+            "{([(<{}[<>[]}>{[]{[(<()>",
+            "[[<[([]))<([[{}[[()]]]",
+            "[{[{({}]{}}([{[{{{}}([]",
+            "[<(<(<(<{}))><([]([]()",
+            "<{([([[(<>()){}]>(<<{{",
+        ] {
+            let repaired = checker.repair(line).unwrap();
+            assert_eq!(
+                LineStatus::Incomplete("".to_string()),
+                checker.line_status(&repaired.line)
+            );
+            assert_eq!(repaired.cost(), levenshtein(line, &repaired.line));
+        }
+
+        // only delimiters count towards the limit
+        let line = format!("{}x", "(]".repeat(MAX_REPAIR_DELIMITERS / 2));
+        let repaired = checker.repair(&line).unwrap();
+        assert_eq!(MAX_REPAIR_DELIMITERS / 2, repaired.cost());
+        let err = checker.repair(&format!("{}(", line)).unwrap_err();
+        assert!(err.to_string().contains("more than"), "{}", err);
+    }
+
+    /// Number of insertions, deletions and replacements to turn `a` into `b`.
+    fn levenshtein(a: &str, b: &str) -> usize {
+        let b = b.chars().collect_vec();
+        let mut row = (0..=b.len()).collect_vec();
+        for (i, c) in a.chars().enumerate() {
+            let mut next = vec![i + 1];
+            for j in 0..b.len() {
+                let replace = row[j] + (c != b[j]) as usize;
+                next.push(replace.min(row[j + 1] + 1).min(next[j] + 1));
+            }
+            row = next;
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn test_repair_is_minimal() {
+        let checker = SyntaxChecker::new();
+        let delimiters = ['(', ')', '[', ']', '{', '}', '<', '>'];
+
+        // every balanced line of up to 6 characters
+        let mut balanced = vec![String::new()];
+        let mut last = vec![String::new()];
+        for _ in 0..3 {
+            let mut next = HashSet::new();
+            for line in &last {
+                for i in 0..=line.len() {
+                    for j in i..=line.len() {
+                        for chunk in checker.syntax.iter() {
+                            next.insert(format!(
+                                "{}{}{}{}{}",
+                                &line[..i],
+                                chunk.opening(),
+                                &line[i..j],
+                                chunk.closing(),
+                                &line[j..]
+                            ));
+                        }
+                    }
+                }
+            }
+            last = next.into_iter().collect();
+            balanced.extend(last.iter().cloned());
+        }
+
+        // every line of up to 3 delimiters can be repaired with at most 2 edits, so into one of
+        // at most 5 characters
+        let mut lines = vec![String::new()];
+        for len in 1..=3 {
+            for line in (0..len)
+                .map(|_| delimiters.iter())
+                .multi_cartesian_product()
+            {
+                lines.push(line.into_iter().collect());
+            }
+        }
+        for line in lines {
+            let repair = checker.repair(&line).unwrap();
+            let best = balanced
+                .iter()
+                .map(|target| levenshtein(&line, target))
+                .min()
+                .unwrap();
+            assert_eq!(best, repair.cost(), "{}", line);
+            assert_eq!(repair.cost(), levenshtein(&line, &repair.line), "{}", line);
+            assert_eq!(
+                LineStatus::Incomplete("".to_string()),
+                checker.line_status(&repair.line)
+            );
+        }
     }
 }
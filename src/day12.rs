@@ -1,122 +1,584 @@
-use std::iter::from_fn;
+use std::{
+    collections::{HashMap, HashSet},
+    iter::from_fn,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
 
-use anyhow::{Context, Result};
-use internment::Intern;
+use anyhow::{bail, ensure, Context, Result};
 use itertools::Itertools;
 use petgraph::prelude::*;
 
-type Cave = Intern<String>;
+/// Index of a cave in its `CaveSystem`.
+type Cave = usize;
 type Caves = UnGraphMap<Cave, ()>;
 
 pub fn run() -> Result<()> {
     let content = std::fs::read_to_string("inputs/day12.txt")?;
+    let caves = CaveSystem::from_str(&content)?;
 
-    let mut graph: Caves = UnGraphMap::new();
-    // build the graph from the data
-    // note: we intern the strings to not have to deal with lifetimes, and to avoid too many
-    // allocations
-    for line in content.lines() {
-        let (from, to) = line.split_once('-').context("Invalid edge")?;
-        graph.add_edge(Intern::from(from), Intern::from(to), ());
-    }
-
-    let paths = all_paths(
-        &graph,
-        Intern::from("start"),
-        Intern::from("end"),
-        allow_cave1,
-    )
-    .count();
+    let policy = RevisitPolicy::part1(&caves)?;
+    let paths = count_paths(&caves, caves.start, caves.end, &policy);
     println!("day12 part1 = {}", paths);
 
-    let paths = all_paths(
-        &graph,
-        Intern::from("start"),
-        Intern::from("end"),
-        allow_cave2,
-    )
-    .count();
+    let policy = RevisitPolicy::part2(&caves)?;
+    let paths = count_paths(&caves, caves.start, caves.end, &policy);
     println!("day12 part2 = {}", paths);
 
     Ok(())
 }
 
-// adapted from https://github.com/petgraph/petgraph/blob/master/src/algo/simple_paths.rs#L36
-pub fn all_paths<'a, F>(
-    graph: &'a Caves,
+/// Print every path through the caves described in the given file (the puzzle input by default).
+///
+/// Small caves are visited once unless `--twice` (the part 2 rules) or the `visits=N`,
+/// `revisited=N` and `once=CAVE,...` settings say otherwise. With `threads=N`, the search is shared
+/// between N threads.
+pub fn list_paths(args: &[String]) -> Result<()> {
+    let mut path = "inputs/day12.txt";
+    let mut max_visits = 1;
+    let mut max_revisited = 0;
+    let mut once = "start,end";
+    let mut threads = None;
+    for arg in args {
+        if arg == "--twice" {
+            max_visits = 2;
+            max_revisited = 1;
+        } else if let Some((name, value)) = arg.split_once('=') {
+            match name {
+                "visits" => max_visits = value.parse().context("Invalid visits")?,
+                "revisited" => max_revisited = value.parse().context("Invalid revisited")?,
+                "once" => once = value,
+                "threads" => threads = Some(value.parse().context("Invalid threads")?),
+                _ => bail!("Unknown setting {}", name),
+            }
+        } else {
+            path = arg;
+        }
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    let caves = CaveSystem::from_str(&content)?;
+    let once = once
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let policy = RevisitPolicy::new(&caves, max_visits, max_revisited, &once)?;
+    let paths: Box<dyn Iterator<Item = Vec<Cave>>> = match threads {
+        Some(threads) => {
+            Box::new(par_all_paths(&caves, caves.start, caves.end, &policy, threads).into_iter())
+        }
+        None => Box::new(all_paths(&caves, caves.start, caves.end, &policy)),
+    };
+    for path in paths {
+        println!("{}", path.iter().map(|c| caves.name(*c)).join(","));
+    }
+
+    Ok(())
+}
+
+/// Print the cave system described in the given file (the puzzle input by default) in the DOT
+/// format, highlighting the path given by a `path=CAVE,...` setting.
+pub fn export_dot(args: &[String]) -> Result<()> {
+    let mut file = "inputs/day12.txt";
+    let mut path = None;
+    for arg in args {
+        match arg.split_once('=') {
+            Some(("path", value)) => path = Some(value),
+            Some((name, _)) => bail!("Unknown setting {}", name),
+            None => file = arg,
+        }
+    }
+
+    let content = std::fs::read_to_string(file)?;
+    let caves = CaveSystem::from_str(&content)?;
+    let path = match path {
+        Some(path) => caves.parse_path(path)?,
+        None => vec![],
+    };
+    print!("{}", caves.to_dot(&path));
+
+    Ok(())
+}
+
+/// The caves and the passages between them.
+///
+/// Caves are numbered in the order they appear in the input, so that sets of caves can be
+/// represented as bitsets.
+#[derive(Debug)]
+pub struct CaveSystem {
+    graph: Caves,
+    names: Vec<String>,
+    /// Bitset of the small caves.
+    small: u64,
+    start: Cave,
+    end: Cave,
+}
+
+impl CaveSystem {
+    fn name(&self, cave: Cave) -> &str {
+        &self.names[cave]
+    }
+
+    fn id(&self, name: &str) -> Option<Cave> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    fn is_small(&self, cave: Cave) -> bool {
+        self.small & (1 << cave) != 0
+    }
+
+    /// Parse a comma separated list of caves, each connected to the previous one.
+    fn parse_path(&self, s: &str) -> Result<Vec<Cave>> {
+        let path = s
+            .split(',')
+            .map(|name| {
+                self.id(name)
+                    .with_context(|| format!("Unknown cave {}", name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (a, b) in path.iter().tuple_windows() {
+            ensure!(
+                self.graph.contains_edge(*a, *b),
+                "Caves {} and {} are not connected",
+                self.name(*a),
+                self.name(*b)
+            );
+        }
+
+        Ok(path)
+    }
+
+    /// Describe the caves in the DOT format used by Graphviz. Big caves are drawn as boxes and
+    /// small caves as ellipses, and the caves and passages of `path` are highlighted.
+    fn to_dot(&self, path: &[Cave]) -> String {
+        let on_path = path.iter().copied().collect::<HashSet<_>>();
+        let steps = path
+            .iter()
+            .tuple_windows()
+            .flat_map(|(a, b)| [(*a, *b), (*b, *a)])
+            .collect::<HashSet<_>>();
+
+        let mut out = String::from("graph caves {\n");
+        for cave in self.graph.nodes() {
+            let mut attributes = vec![if self.is_small(cave) {
+                "shape=ellipse"
+            } else {
+                "shape=box, style=bold"
+            }];
+            if cave == self.start || cave == self.end {
+                attributes.push("peripheries=2");
+            }
+            if on_path.contains(&cave) {
+                attributes.push("color=red, fontcolor=red");
+            }
+            out += &format!("    \"{}\" [{}];\n", self.name(cave), attributes.join(", "));
+        }
+        for (a, b, _) in self.graph.all_edges() {
+            let attributes = if steps.contains(&(a, b)) {
+                " [color=red, penwidth=2]"
+            } else {
+                ""
+            };
+            out += &format!(
+                "    \"{}\" -- \"{}\"{};\n",
+                self.name(a),
+                self.name(b),
+                attributes
+            );
+        }
+        out.push_str("}\n");
+
+        out
+    }
+}
+
+impl FromStr for CaveSystem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut graph: Caves = UnGraphMap::new();
+        let mut names: Vec<String> = Vec::new();
+        let mut ids: HashMap<&str, Cave> = HashMap::new();
+        let mut small = 0u64;
+        for line in s.lines() {
+            let (from, to) = line.split_once('-').context("Invalid edge")?;
+            let mut ends = [0; 2];
+            for (end, name) in ends.iter_mut().zip([from, to]) {
+                ensure!(!name.is_empty(), "Empty cave name");
+                *end = match ids.get(name) {
+                    Some(id) => *id,
+                    None => {
+                        let id = names.len();
+                        ensure!(id < 64, "Too many caves");
+                        if name.as_bytes()[0].is_ascii_lowercase() {
+                            small |= 1 << id;
+                        }
+                        names.push(name.to_string());
+                        ids.insert(name, id);
+                        id
+                    }
+                };
+            }
+            graph.add_edge(ends[0], ends[1], ());
+        }
+        // Big caves can be visited any number of times, so going back and forth between two of them
+        // would give an infinite number of paths.
+        for (a, b, _) in graph.all_edges() {
+            ensure!(
+                (small >> a | small >> b) & 1 != 0,
+                "Infinite number of paths: big caves {} and {} are connected",
+                names[a],
+                names[b]
+            );
+        }
+        let start = *ids.get("start").context("No start cave")?;
+        let end = *ids.get("end").context("No end cave")?;
+
+        Ok(Self {
+            graph,
+            names,
+            small,
+            start,
+            end,
+        })
+    }
+}
+
+/// Which caves a path may go through, and how many times. Big caves can always be visited again.
+#[derive(Debug, Clone)]
+pub struct RevisitPolicy {
+    /// How many times a small cave may be visited.
+    max_visits: u8,
+    /// How many small caves may be visited more than once by a single path.
+    max_revisited: u32,
+    /// Bitset of the caves that may never be visited twice.
+    once: u64,
+}
+
+impl RevisitPolicy {
+    pub fn new(
+        caves: &CaveSystem,
+        max_visits: u8,
+        max_revisited: u32,
+        once: &[&str],
+    ) -> Result<Self> {
+        ensure!(max_visits > 0, "Small caves must be visited at least once");
+        let mut mask = 0;
+        for name in once {
+            let cave = caves
+                .id(name)
+                .with_context(|| format!("Unknown cave {}", name))?;
+            mask |= 1 << cave;
+        }
+
+        Ok(Self {
+            max_visits,
+            max_revisited,
+            once: mask,
+        })
+    }
+
+    /// Small caves are visited at most once (part 1).
+    pub fn part1(caves: &CaveSystem) -> Result<Self> {
+        Self::new(caves, 1, 0, &["start", "end"])
+    }
+
+    /// A single small cave other than the start and the end may be visited twice (part 2).
+    pub fn part2(caves: &CaveSystem) -> Result<Self> {
+        Self::new(caves, 2, 1, &["start", "end"])
+    }
+
+    /// Whether a path that went through `visited` may continue to `cave`.
+    fn allows(&self, caves: &CaveSystem, cave: Cave, visited: &Visited) -> bool {
+        let visits = visited.visits(cave);
+        if visits == 0 {
+            true
+        } else if self.once & (1 << cave) != 0 {
+            false
+        } else if !caves.is_small(cave) {
+            true
+        } else {
+            visits < self.max_visits
+                && (visits > 1 || visited.revisited(caves) < self.max_revisited)
+        }
+    }
+
+    /// Caves whose visits must be counted to apply the policy.
+    fn tracked(&self, caves: &CaveSystem) -> u64 {
+        caves.small | self.once
+    }
+}
+
+/// The small caves a path went through (and the caves that may only be visited once), and how
+/// many times.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Visited {
+    /// Bitset of the caves visited at least once.
+    seen: u64,
+    /// Bitset of the caves visited at least twice.
+    seen_twice: u64,
+    /// Visits of each cave beyond the second one, only counted if the policy allows more than two
+    /// visits.
+    extra_visits: Option<Box<[u8; 64]>>,
+}
+
+impl Visited {
+    fn new(policy: &RevisitPolicy) -> Self {
+        Self {
+            seen: 0,
+            seen_twice: 0,
+            extra_visits: (policy.max_visits > 2).then(|| Box::new([0; 64])),
+        }
+    }
+
+    fn visits(&self, cave: Cave) -> u8 {
+        let bit = 1 << cave;
+        if self.seen & bit == 0 {
+            0
+        } else if self.seen_twice & bit == 0 {
+            1
+        } else {
+            2 + self.extra_visits.as_ref().map_or(0, |extra| extra[cave])
+        }
+    }
+
+    /// How many small caves were visited more than once.
+    fn revisited(&self, caves: &CaveSystem) -> u32 {
+        (self.seen_twice & caves.small).count_ones()
+    }
+
+    /// Record a visit of `cave`, if it's one of the `tracked` caves.
+    fn enter(&mut self, tracked: u64, cave: Cave) {
+        let bit = 1 << cave;
+        if tracked & bit == 0 {
+            return;
+        }
+        if self.seen & bit == 0 {
+            self.seen |= bit;
+        } else if self.seen_twice & bit == 0 {
+            self.seen_twice |= bit;
+        } else {
+            let extra = self
+                .extra_visits
+                .as_mut()
+                .expect("The policy doesn't allow more than two visits");
+            extra[cave] += 1;
+        }
+    }
+
+    /// Undo a call to `enter`.
+    fn leave(&mut self, tracked: u64, cave: Cave) {
+        let bit = 1 << cave;
+        if tracked & bit == 0 {
+            return;
+        }
+        match &mut self.extra_visits {
+            Some(extra) if extra[cave] > 0 => extra[cave] -= 1,
+            _ if self.seen_twice & bit != 0 => self.seen_twice &= !bit,
+            _ => self.seen &= !bit,
+        }
+    }
+}
+
+/// Every path from `from` to `to` allowed by `policy`.
+pub fn all_paths<'a>(
+    caves: &'a CaveSystem,
     from: Cave,
     to: Cave,
-    allow_cave: F,
-) -> impl Iterator<Item = Vec<Cave>> + 'a
-where
-    F: Fn(Cave, &[Cave]) -> bool + 'a,
-{
+    policy: &'a RevisitPolicy,
+) -> impl Iterator<Item = Vec<Cave>> + 'a {
+    paths_from(caves, vec![from], to, policy)
+}
+
+// adapted from https://github.com/petgraph/petgraph/blob/master/src/algo/simple_paths.rs#L36
+/// Every path to `to` allowed by `policy` that starts with `prefix`.
+fn paths_from<'a>(
+    caves: &'a CaveSystem,
+    prefix: Vec<Cave>,
+    to: Cave,
+    policy: &'a RevisitPolicy,
+) -> impl Iterator<Item = Vec<Cave>> + 'a {
+    let tracked = policy.tracked(caves);
+    let mut complete = prefix.last() == Some(&to);
     // list of visited nodes (i.e. the current path so far)
-    let mut visited: Vec<Cave> = Vec::from_iter(Some(from));
+    let mut path: Vec<Cave> = prefix;
+    let mut visited = Visited::new(policy);
+    for cave in &path {
+        visited.enter(tracked, *cave);
+    }
     // list of children of currently exploring path nodes,
     // last elem is list of children of last visited node
     // Note: each element is *an iterator*, i.e. it keeps its state of where it's at even when we
     // explore the next level.
-    let mut stack = vec![graph.neighbors(from)];
+    let mut stack = match path.last() {
+        Some(last) if !complete => vec![caves.graph.neighbors(*last)],
+        _ => vec![],
+    };
 
     from_fn(move || {
+        if complete {
+            // The prefix is already a full path
+            complete = false;
+            return Some(path.clone());
+        }
         while let Some(children) = stack.last_mut() {
             if let Some(child) = children.next() {
                 if child == to {
                     // We reached the end node: return the current path as the next value of the
                     // iterator
-                    let path = visited
-                        .iter()
-                        .cloned()
-                        .chain(Some(to))
-                        .collect::<Vec<Cave>>();
+                    let path = path.iter().cloned().chain(Some(to)).collect::<Vec<Cave>>();
                     return Some(path);
-                } else if allow_cave(child, &visited) {
+                } else if policy.allows(caves, child, &visited) {
                     // Keep exploring paths (if we're allowed to)
-                    visited.push(child);
-                    stack.push(graph.neighbors(child));
+                    visited.enter(tracked, child);
+                    path.push(child);
+                    stack.push(caves.graph.neighbors(child));
                 }
             } else {
                 // we've exhausted all the children of the current node: backtrack to the previous
                 // last visited node. This is the equivalent of a "return" if this function was
                 // written using recursion.
                 stack.pop();
-                visited.pop();
+                if let Some(cave) = path.pop() {
+                    visited.leave(tracked, cave);
+                }
             }
         }
         None
     })
 }
 
-fn allow_cave1(cave: Cave, visited: &[Cave]) -> bool {
-    !is_small_cave(cave) || !visited.contains(&cave)
+/// How many levels of the search `par_all_paths` may split before handing it to the threads.
+const MAX_SPLIT_DEPTH: usize = 4;
+
+/// Same as `all_paths`, but the search is shared between `threads` threads. The paths are
+/// returned in the same order as `all_paths`.
+pub fn par_all_paths(
+    caves: &CaveSystem,
+    from: Cave,
+    to: Cave,
+    policy: &RevisitPolicy,
+    threads: usize,
+) -> Vec<Vec<Cave>> {
+    let tasks = split_paths(caves, from, to, policy, threads * 16);
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let task = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(prefix) = tasks.get(task) else {
+                            break done;
+                        };
+                        let paths = paths_from(caves, prefix.clone(), to, policy).collect_vec();
+                        done.push((task, paths));
+                    }
+                })
+            })
+            .collect_vec();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect_vec()
+    });
+
+    // the tasks are in the order `all_paths` would reach them
+    results.sort_by_key(|(task, _)| *task);
+    results.into_iter().flat_map(|(_, paths)| paths).collect()
 }
 
-fn allow_cave2(cave: Cave, visited: &[Cave]) -> bool {
-    !is_small_cave(cave)
-        || !visited.contains(&cave)
-        || (!is_start(cave) && !has_duplicate_small_cave(visited))
+/// Split the search for paths from `from` to `to` into at least `min_tasks` path prefixes, if
+/// possible, by expanding the first levels of the search. Prefixes that end with `to` are full
+/// paths.
+fn split_paths(
+    caves: &CaveSystem,
+    from: Cave,
+    to: Cave,
+    policy: &RevisitPolicy,
+    min_tasks: usize,
+) -> Vec<Vec<Cave>> {
+    let tracked = policy.tracked(caves);
+    let mut tasks = vec![vec![from]];
+    for _ in 0..MAX_SPLIT_DEPTH {
+        if tasks.len() >= min_tasks || tasks.iter().all(|task| task.last() == Some(&to)) {
+            break;
+        }
+        let mut next = vec![];
+        for task in tasks {
+            let last = *task.last().expect("Empty prefix");
+            if last == to {
+                next.push(task);
+                continue;
+            }
+            let mut visited = Visited::new(policy);
+            for cave in &task {
+                visited.enter(tracked, *cave);
+            }
+            for child in caves.graph.neighbors(last) {
+                if child == to || policy.allows(caves, child, &visited) {
+                    next.push(task.iter().copied().chain(Some(child)).collect());
+                }
+            }
+        }
+        tasks = next;
+    }
+
+    tasks
 }
 
-fn is_small_cave(c: Cave) -> bool {
-    c.as_ref().bytes().next().unwrap().is_ascii_lowercase()
+/// Count the paths from `from` to `to` allowed by `policy`.
+///
+/// Unlike `all_paths`, this doesn't build the paths: the number of ways to finish a path only
+/// depends on the current cave and the visits of the small caves so far, so it is only computed
+/// once for each of these states.
+pub fn count_paths(caves: &CaveSystem, from: Cave, to: Cave, policy: &RevisitPolicy) -> u64 {
+    let mut counter = PathCounter {
+        caves,
+        to,
+        policy,
+        tracked: policy.tracked(caves),
+        cache: HashMap::new(),
+    };
+    let mut visited = Visited::new(policy);
+    visited.enter(counter.tracked, from);
+    counter.count(from, visited)
 }
 
-fn is_start(c: Cave) -> bool {
-    c == Intern::from("start")
+struct PathCounter<'a> {
+    caves: &'a CaveSystem,
+    to: Cave,
+    policy: &'a RevisitPolicy,
+    tracked: u64,
+    cache: HashMap<(Cave, Visited), u64>,
 }
 
-fn has_duplicate_small_cave(list: &[Cave]) -> bool {
-    let start = Intern::from("start");
-    let end = Intern::from("end");
-    let counts = list
-        .iter()
-        .copied()
-        // only keep small caves that neither start nor end
-        .filter(|s| is_small_cave(*s) && *s != start && *s != end)
-        .counts();
-    // dbg!(&counts);
-    counts.values().contains(&2)
+impl<'a> PathCounter<'a> {
+    /// Number of ways to get to the end from `cave`, given the small caves visited so far.
+    fn count(&mut self, cave: Cave, visited: Visited) -> u64 {
+        if cave == self.to {
+            return 1;
+        }
+        let key = (cave, visited);
+        if let Some(count) = self.cache.get(&key) {
+            return *count;
+        }
+        let (_, visited) = &key;
+
+        let mut count = 0;
+        for next in self.caves.graph.neighbors(cave) {
+            if self.policy.allows(self.caves, next, visited) {
+                let mut visited = visited.clone();
+                visited.enter(self.tracked, next);
+                count += self.count(next, visited);
+            }
+        }
+        self.cache.insert(key, count);
+
+        count
+    }
 }
 
 #[cfg(test)]
@@ -133,57 +595,358 @@ b-d
 A-end
 b-end";
 
-        let mut graph = UnGraphMap::new();
-        for line in content.lines() {
-            let (from, to) = line.split_once('-').context("Invalid edge").unwrap();
-            graph.add_edge(Intern::from(from), Intern::from(to), ());
-        }
+        let caves = CaveSystem::from_str(content).unwrap();
+        let part1 = RevisitPolicy::part1(&caves).unwrap();
+        let part2 = RevisitPolicy::part2(&caves).unwrap();
 
-        let paths = all_paths(
-            &graph,
-            Intern::from("start"),
-            Intern::from("end"),
-            allow_cave1,
-        )
-        .count();
+        let paths = all_paths(&caves, caves.start, caves.end, &part1).count();
         assert_eq!(10, paths);
 
-        let paths = all_paths(
-            &graph,
-            Intern::from("start"),
-            Intern::from("end"),
-            allow_cave2,
-        )
-        .count();
+        let paths = all_paths(&caves, caves.start, caves.end, &part2).count();
 
         assert_eq!(36, paths);
+
+        assert_eq!(10, count_paths(&caves, caves.start, caves.end, &part1));
+        assert_eq!(36, count_paths(&caves, caves.start, caves.end, &part2));
     }
 
     #[test]
-    fn has_duplicates() {
-        let list = vec![
-            Intern::from("start"),
-            Intern::from("A"),
-            Intern::from("b"),
-            Intern::from("A"),
-            Intern::from("c"),
-            Intern::from("A"),
-            Intern::from("b"),
-            Intern::from("A"),
-            Intern::from("end"),
+    fn test_count_paths() {
+        let examples = [
+            (
+                r"dc-end
+HN-start
+start-kj
+dc-start
+dc-HN
+LN-dc
+HN-end
+kj-sa
+kj-HN
+kj-dc",
+                19,
+                103,
+            ),
+            (
+                r"fs-end
+he-DX
+fs-he
+start-DX
+pj-DX
+end-zg
+zg-sl
+zg-pj
+pj-he
+RW-he
+fs-DX
+pj-RW
+zg-RW
+start-pj
+he-WI
+zg-he
+pj-fs
+start-RW",
+                226,
+                3509,
+            ),
         ];
-        assert!(has_duplicate_small_cave(&list));
-
-        let list = vec![
-            Intern::from("start"),
-            Intern::from("A"),
-            Intern::from("b"),
-            Intern::from("A"),
-            Intern::from("c"),
-            Intern::from("A"),
-            Intern::from("A"),
-            Intern::from("end"),
+        for (content, part1, part2) in examples {
+            let caves = CaveSystem::from_str(content).unwrap();
+            let policy1 = RevisitPolicy::part1(&caves).unwrap();
+            let policy2 = RevisitPolicy::part2(&caves).unwrap();
+
+            assert_eq!(part1, count_paths(&caves, caves.start, caves.end, &policy1));
+            assert_eq!(part2, count_paths(&caves, caves.start, caves.end, &policy2));
+            assert_eq!(
+                part2,
+                all_paths(&caves, caves.start, caves.end, &policy2).count() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn has_duplicates() {
+        let caves = CaveSystem::from_str("start-A\nA-b\nA-c\nA-end").unwrap();
+        let policy = RevisitPolicy::part2(&caves).unwrap();
+        let tracked = policy.tracked(&caves);
+        let id = |name: &str| caves.id(name).unwrap();
+
+        let mut visited = Visited::new(&policy);
+        for name in ["start", "A", "b", "A", "c", "A", "b", "A", "end"] {
+            visited.enter(tracked, id(name));
+        }
+        assert_eq!(1, visited.revisited(&caves));
+        assert_eq!(
+            (2, 1, 0),
+            (
+                visited.visits(id("b")),
+                visited.visits(id("c")),
+                visited.visits(id("A"))
+            )
+        );
+
+        // leaving the second visit of b forgets about it, but not about the first one
+        visited.leave(tracked, id("b"));
+        assert_eq!(0, visited.revisited(&caves));
+        assert_eq!(1, visited.visits(id("b")));
+
+        let mut visited = Visited::new(&policy);
+        for name in ["start", "A", "b", "A", "c", "A", "A", "end"] {
+            visited.enter(tracked, id(name));
+        }
+        assert_eq!(0, visited.revisited(&caves));
+
+        // visits beyond the second are only counted when the policy allows them
+        let policy = RevisitPolicy::new(&caves, 3, 1, &["start", "end"]).unwrap();
+        let mut visited = Visited::new(&policy);
+        for name in ["start", "A", "b", "A", "b", "A", "b"] {
+            visited.enter(tracked, id(name));
+        }
+        assert_eq!((3, 1), (visited.visits(id("b")), visited.revisited(&caves)));
+        visited.leave(tracked, id("b"));
+        assert_eq!((2, 1), (visited.visits(id("b")), visited.revisited(&caves)));
+        visited.leave(tracked, id("b"));
+        assert_eq!((1, 0), (visited.visits(id("b")), visited.revisited(&caves)));
+    }
+
+    #[test]
+    fn test_policy() {
+        let content = "start-A\nstart-b\nA-c\nA-b\nb-d\nA-end\nb-end";
+        let caves = CaveSystem::from_str(content).unwrap();
+        let count = |max_visits, max_revisited, once: &[&str]| {
+            let policy = RevisitPolicy::new(&caves, max_visits, max_revisited, once).unwrap();
+            let paths = all_paths(&caves, caves.start, caves.end, &policy).collect::<Vec<_>>();
+            assert_eq!(
+                paths.len() as u64,
+                count_paths(&caves, caves.start, caves.end, &policy)
+            );
+            paths
+        };
+
+        // the puzzle rules, spelled out
+        assert_eq!(10, count(1, 0, &["start", "end"]).len());
+        assert_eq!(36, count(2, 1, &["start", "end"]).len());
+        // raising the number of visits without letting any cave be revisited changes nothing
+        assert_eq!(10, count(3, 0, &["start", "end"]).len());
+
+        // more revisits give more paths
+        let paths = count(2, 2, &["start", "end"]);
+        assert!(paths.len() > 36);
+        let paths = count(3, 1, &["start", "end"]);
+        assert!(paths.len() > 36);
+
+        // b is never visited twice
+        let b = caves.id("b").unwrap();
+        let paths = count(2, 1, &["start", "end", "b"]);
+        assert!(paths.len() > 10 && paths.len() < 36);
+        assert!(paths
+            .iter()
+            .all(|path| path.iter().filter(|cave| **cave == b).count() <= 1));
+
+        // the start can be visited again if it isn't excluded
+        let paths = count(2, 1, &["end"]);
+        assert!(paths
+            .iter()
+            .any(|path| path.iter().filter(|cave| **cave == caves.start).count() == 2));
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        let caves = CaveSystem::from_str("start-A\nA-b\nb-end").unwrap();
+        assert!(RevisitPolicy::new(&caves, 0, 0, &[]).is_err());
+        assert!(RevisitPolicy::new(&caves, 1, 0, &["start", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let caves = CaveSystem::from_str("start-A\nA-b\nb-end").unwrap();
+        assert_eq!(vec!["start", "A", "b", "end"], caves.names);
+        assert_eq!((0, 3), (caves.start, caves.end));
+        assert!(caves.is_small(0) && !caves.is_small(1) && caves.is_small(2));
+
+        // both ends of a loop are the same cave
+        let caves = CaveSystem::from_str("start-c\nc-c\nc-end").unwrap();
+        assert_eq!(vec!["start", "c", "end"], caves.names);
+
+        assert!(CaveSystem::from_str("start-A\nA-b").is_err());
+        assert!(CaveSystem::from_str("start-A\nA_end").is_err());
+        assert!(CaveSystem::from_str("start-\nA-end").is_err());
+
+        let error = CaveSystem::from_str("start-A\nA-b\nb-C\nC-A\nA-end").unwrap_err();
+        assert_eq!(
+            "Infinite number of paths: big caves A and C are connected",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_par_all_paths() {
+        let examples = [
+            "start-A\nstart-b\nA-c\nA-b\nb-d\nA-end\nb-end",
+            "dc-end\nHN-start\nstart-kj\ndc-start\ndc-HN\nLN-dc\nHN-end\nkj-sa\nkj-HN\nkj-dc",
+            "start-end\nstart-a\na-end",
         ];
-        assert!(!has_duplicate_small_cave(&list));
+        for content in examples {
+            let caves = CaveSystem::from_str(content).unwrap();
+            for policy in [
+                RevisitPolicy::part1(&caves).unwrap(),
+                RevisitPolicy::part2(&caves).unwrap(),
+            ] {
+                let paths = all_paths(&caves, caves.start, caves.end, &policy).collect_vec();
+                for threads in [0, 1, 2, 3, 8] {
+                    assert_eq!(
+                        paths,
+                        par_all_paths(&caves, caves.start, caves.end, &policy, threads)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_paths() {
+        let caves = CaveSystem::from_str("start-A\nstart-b\nA-c\nA-b\nb-d\nA-end\nb-end").unwrap();
+        let policy = RevisitPolicy::part1(&caves).unwrap();
+        let names = |paths: Vec<Vec<Cave>>| {
+            paths
+                .iter()
+                .map(|path| path.iter().map(|c| caves.name(*c)).join(","))
+                .collect_vec()
+        };
+
+        let tasks = split_paths(&caves, caves.start, caves.end, &policy, 1);
+        assert_eq!(vec!["start"], names(tasks));
+        let tasks = split_paths(&caves, caves.start, caves.end, &policy, 2);
+        assert_eq!(vec!["start,A", "start,b"], names(tasks));
+        let tasks = split_paths(&caves, caves.start, caves.end, &policy, 3);
+        assert_eq!(
+            vec![
+                "start,A,c",
+                "start,A,b",
+                "start,A,end",
+                "start,b,A",
+                "start,b,d",
+                "start,b,end"
+            ],
+            names(tasks)
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let caves = CaveSystem::from_str("start-A\nA-b\nA-end").unwrap();
+        assert_eq!(
+            r#"graph caves {
+    "start" [shape=ellipse, peripheries=2];
+    "A" [shape=box, style=bold];
+    "b" [shape=ellipse];
+    "end" [shape=ellipse, peripheries=2];
+    "start" -- "A";
+    "A" -- "b";
+    "A" -- "end";
+}
+"#,
+            caves.to_dot(&[])
+        );
+
+        let path = caves.parse_path("start,A,b,A,end").unwrap();
+        assert_eq!(
+            r#"graph caves {
+    "start" [shape=ellipse, peripheries=2, color=red, fontcolor=red];
+    "A" [shape=box, style=bold, color=red, fontcolor=red];
+    "b" [shape=ellipse, color=red, fontcolor=red];
+    "end" [shape=ellipse, peripheries=2, color=red, fontcolor=red];
+    "start" -- "A" [color=red, penwidth=2];
+    "A" -- "b" [color=red, penwidth=2];
+    "A" -- "end" [color=red, penwidth=2];
+}
+"#,
+            caves.to_dot(&path)
+        );
+
+        let path = caves.parse_path("start,A,end").unwrap();
+        assert!(caves.to_dot(&path).contains("    \"b\" [shape=ellipse];\n"));
+
+        assert!(caves.parse_path("start,b").is_err());
+        assert!(caves.parse_path("start,x").is_err());
+    }
+
+    /// The part 2 path search as it was before caves were indexed as bits, scanning the current
+    /// path to find out whether a cave may be visited. Only kept as a reference for the benchmark.
+    fn scan_all_paths(caves: &CaveSystem) -> Vec<Vec<Cave>> {
+        let is_small = |c: Cave| caves.name(c).as_bytes()[0].is_ascii_lowercase();
+        let has_duplicate_small_cave = |visited: &[Cave]| {
+            visited
+                .iter()
+                .copied()
+                .filter(|c| is_small(*c) && *c != caves.start && *c != caves.end)
+                .counts()
+                .values()
+                .contains(&2)
+        };
+        let allow_cave = |cave: Cave, visited: &[Cave]| {
+            !is_small(cave)
+                || !visited.contains(&cave)
+                || (cave != caves.start && !has_duplicate_small_cave(visited))
+        };
+
+        let mut paths = vec![];
+        let mut visited = vec![caves.start];
+        let mut stack = vec![caves.graph.neighbors(caves.start)];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(child) if child == caves.end => {
+                    paths.push(visited.iter().copied().chain(Some(child)).collect_vec())
+                }
+                Some(child) => {
+                    if allow_cave(child, &visited) {
+                        visited.push(child);
+                        stack.push(caves.graph.neighbors(child));
+                    }
+                }
+                None => {
+                    stack.pop();
+                    visited.pop();
+                }
+            }
+        }
+
+        paths
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_all_paths() {
+        let content = std::fs::read_to_string("inputs/day12.txt").unwrap();
+        let caves = CaveSystem::from_str(&content).unwrap();
+        let policy = RevisitPolicy::part2(&caves).unwrap();
+
+        let start = std::time::Instant::now();
+        let reference = scan_all_paths(&caves).len();
+        println!(
+            "path scans (part 2): {} paths in {:?}",
+            reference,
+            start.elapsed()
+        );
+
+        let start = std::time::Instant::now();
+        let paths = all_paths(&caves, caves.start, caves.end, &policy).count();
+        println!(
+            "all_paths (part 2): {} paths in {:?}",
+            paths,
+            start.elapsed()
+        );
+        assert_eq!(reference, paths);
+
+        let threads = thread::available_parallelism().unwrap().get();
+        let start = std::time::Instant::now();
+        let paths = par_all_paths(&caves, caves.start, caves.end, &policy, threads).len();
+        println!(
+            "par_all_paths (part 2, {} threads): {} paths in {:?}",
+            threads,
+            paths,
+            start.elapsed()
+        );
     }
 }
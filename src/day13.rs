@@ -1,88 +1,358 @@
 use std::{
     collections::{HashSet, VecDeque},
     fmt::Display,
+    iter::from_fn,
+    path::Path,
     str::FromStr,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Either;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use regex::Regex;
 
 pub fn run() -> Result<()> {
     let content = std::fs::read_to_string("inputs/day13.txt")?;
 
-    let mut dots = vec![];
-    let mut folds = vec![];
-    let mut parsing_positions = true;
-    for line in content.lines() {
-        if line.is_empty() {
-            parsing_positions = false;
-            continue;
-        }
+    let mut paper = Paper::from_str(&content)?;
+    paper.fold_once()?;
+    println!("day13 part1 = {}", paper.count_dots());
 
-        if parsing_positions {
-            dots.push(Pos::from_str(line)?);
-        } else {
-            // Parse instruction
-            folds.push(Fold::from_str(line)?);
+    paper.fold_all()?;
+    match paper.read() {
+        Ok(text) => println!("day13 part2 = {}", text),
+        Err(_) => println!("day13 part2 = \n{}", paper),
+    }
+
+    Ok(())
+}
+
+/// Print the size of the paper after each fold, and its final state. With a directory, the
+/// paper is also saved there before and after each fold, as text or as PBM images (with
+/// `format=pbm`).
+pub fn history(args: &[String]) -> Result<()> {
+    let mut dir = None;
+    let mut pbm = false;
+    for arg in args {
+        match arg.split_once('=') {
+            Some(("format", "text")) => pbm = false,
+            Some(("format", "pbm")) => pbm = true,
+            Some(("format", format)) => bail!("Unknown format {}", format),
+            Some((name, _)) => bail!("Unknown setting {}", name),
+            None => dir = Some(Path::new(arg)),
         }
     }
-    let mut paper = Paper::new(dots, folds);
-    paper.fold_once();
-    println!("day13 part1 = {}", paper.count_dots());
+    if let Some(dir) = dir {
+        std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    let save = |frame: usize, paper: &Paper| -> Result<()> {
+        if let Some(dir) = dir {
+            let (content, extension) = if pbm {
+                (paper.to_pbm(), "pbm")
+            } else {
+                (paper.to_string(), "txt")
+            };
+            let path = dir.join(format!("fold-{:02}.{}", frame, extension));
+            std::fs::write(&path, content)
+                .with_context(|| format!("Cannot write {}", path.display()))?;
+        }
+        Ok(())
+    };
+
+    let content = std::fs::read_to_string("inputs/day13.txt")?;
+    let mut paper = Paper::from_str(&content)?;
+    println!(
+        "before folding: {} dots, {}x{}",
+        paper.count_dots(),
+        paper.width,
+        paper.height
+    );
+    save(0, &paper)?;
+    let mut frame = 0;
+    paper.fold_all_with(|fold, paper| {
+        frame += 1;
+        println!(
+            "{}: {} dots, {}x{}",
+            fold,
+            paper.count_dots(),
+            paper.width,
+            paper.height
+        );
+        save(frame, paper)
+    })?;
+    print!("{}", paper);
 
-    paper.fold_all();
-    println!("day13 part2 = \n{}", paper);
+    Ok(())
+}
+
+/// Print a puzzle input whose code is `message`, once the paper is folded `folds` times.
+pub fn generate(message: &str, folds: &str, seed: Option<&str>) -> Result<()> {
+    let folds = folds.parse().context("Invalid number of folds")?;
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.parse().context("Invalid seed")?),
+        None => StdRng::from_entropy(),
+    };
+    print!("{}", Paper::unfolded(message, folds, &mut rng)?.to_input());
 
     Ok(())
 }
 
 #[derive(Debug)]
 struct Paper {
-    positions: HashSet<Pos>,
+    positions: Dots,
     folds: VecDeque<Fold>,
+    width: u32,
+    height: u32,
 }
 
 impl Paper {
-    fn new(positions: Vec<Pos>, folds: Vec<Fold>) -> Self {
-        let positions = HashSet::from_iter(positions.into_iter());
-        let folds = VecDeque::from_iter(folds.into_iter());
+    fn new(width: u32, height: u32, positions: Vec<Pos>, folds: Vec<Fold>) -> Result<Self> {
+        if let Some(p) = positions.iter().find(|p| p.0 >= width || p.1 >= height) {
+            bail!("Dot {},{} is outside of the paper", p.0, p.1);
+        }
+        let positions = Dots::new(width, height, positions);
+        let folds = VecDeque::from_iter(folds);
 
-        Self { positions, folds }
+        Ok(Self {
+            positions,
+            folds,
+            width,
+            height,
+        })
     }
 
-    fn fold(&mut self, fold: &Fold) {
-        let new_set = self
-            .positions
-            .iter()
-            .map(|p| if fold.affects(p) { fold.mirror(p) } else { *p })
-            .collect::<HashSet<_>>();
-        self.positions = new_set;
+    /// Fold the bottom (or right) part of the paper over the top (or left) part. If it's the
+    /// largest part, the paper is shifted so that its top left corner is still at 0,0.
+    ///
+    /// The dots are then stored as a bitmap or a set, whichever suits their density best.
+    fn fold(&mut self, fold: &Fold) -> Result<()> {
+        self.fold_keeping_layout(fold)?;
+        self.positions = std::mem::take(&mut self.positions).with_density(self.width, self.height);
+        Ok(())
     }
 
-    fn fold_once(&mut self) {
+    /// Same as `fold`, without changing how the dots are stored.
+    fn fold_keeping_layout(&mut self, fold: &Fold) -> Result<()> {
+        let (line, size) = match fold {
+            Fold::X(n) => (*n, self.width),
+            Fold::Y(n) => (*n, self.height),
+        };
+        ensure!(line < size, "{:?} is outside of the paper", fold);
+        let folded = line.max(size - 1 - line);
+
+        self.positions = self.positions.fold(fold, folded)?;
+        match fold {
+            Fold::X(_) => self.width = folded,
+            Fold::Y(_) => self.height = folded,
+        }
+
+        Ok(())
+    }
+
+    /// A paper with `message` written on it, in the font used by the puzzle.
+    fn with_message(message: &str) -> Result<Self> {
+        let mut dots = vec![];
+        for (i, c) in message.chars().enumerate() {
+            let (_, rows) = GLYPHS
+                .iter()
+                .find(|(letter, _)| *letter == c)
+                .with_context(|| format!("No glyph for {:?}", c))?;
+            let left = i as u32 * GLYPH_SPACING;
+            for (row, y) in rows.iter().zip(0..) {
+                for (_, x) in row.bytes().zip(left..).filter(|(c, _)| *c == b'#') {
+                    dots.push(Pos(x, y));
+                }
+            }
+        }
+        let width = (message.chars().count() as u32 * GLYPH_SPACING).saturating_sub(1);
+
+        Paper::new(width, GLYPH_HEIGHT, dots, vec![])
+    }
+
+    /// A random paper that reads `message` once folded `folds` times, the other way around
+    /// from `fold_all`.
+    ///
+    /// Each unfold doubles the paper along a random axis, with the fold in the middle so that
+    /// the size of the paper can be found from the input. Every dot ends up on one side of the
+    /// fold or the other, or both.
+    fn unfolded(message: &str, folds: usize, rng: &mut impl Rng) -> Result<Self> {
+        let mut paper = Paper::with_message(message)?;
+        for _ in 0..folds {
+            let fold = if rng.gen() {
+                Fold::X(paper.width)
+            } else {
+                Fold::Y(paper.height)
+            };
+            paper.unfold(fold, rng)?;
+        }
+
+        Ok(paper)
+    }
+
+    /// Undo `fold`, spreading the dots randomly on both sides of the fold line.
+    fn unfold(&mut self, fold: Fold, rng: &mut impl Rng) -> Result<()> {
+        let (line, size) = match fold {
+            Fold::X(n) => (n, self.width),
+            Fold::Y(n) => (n, self.height),
+        };
+        ensure!(line >= size, "{} would fold back onto the paper", fold);
+        let unfolded = line
+            .checked_mul(2)
+            .and_then(|n| n.checked_add(1))
+            .context("Paper too large")?;
+
+        let mut positions = HashSet::with_capacity(self.positions.len() * 2);
+        for p in self.positions.iter() {
+            let mirrored = match fold {
+                Fold::X(n) => Pos(2 * n - p.0, p.1),
+                Fold::Y(n) => Pos(p.0, 2 * n - p.1),
+            };
+            match rng.gen_range(0..3) {
+                0 => positions.insert(p),
+                1 => positions.insert(mirrored),
+                _ => positions.insert(p) | positions.insert(mirrored),
+            };
+        }
+        match fold {
+            Fold::X(_) => self.width = unfolded,
+            Fold::Y(_) => self.height = unfolded,
+        }
+        self.positions = Dots::new(self.width, self.height, positions);
+        self.folds.push_front(fold);
+
+        Ok(())
+    }
+
+    /// The puzzle input describing this paper: its dots, then its folds.
+    fn to_input(&self) -> String {
+        let mut dots = self.positions.iter().collect::<Vec<_>>();
+        dots.sort_by_key(|p| (p.1, p.0));
+        let mut out = String::new();
+        for p in dots {
+            out += &format!("{},{}\n", p.0, p.1);
+        }
+        out.push('\n');
+        for fold in &self.folds {
+            out += &format!("{}\n", fold);
+        }
+        out
+    }
+
+    fn fold_once(&mut self) -> Result<()> {
         if let Some(fold) = self.folds.pop_front() {
-            self.fold(&fold);
+            self.fold(&fold)?;
         }
+        Ok(())
     }
 
-    fn fold_all(&mut self) {
+    fn fold_all(&mut self) -> Result<()> {
+        self.fold_all_with(|_, _| Ok(()))
+    }
+
+    /// Fold the paper along all the remaining folds, calling `on_fold` with each fold and the
+    /// folded paper.
+    fn fold_all_with(
+        &mut self,
+        mut on_fold: impl FnMut(&Fold, &Paper) -> Result<()>,
+    ) -> Result<()> {
         while let Some(fold) = self.folds.pop_front() {
-            self.fold(&fold);
+            self.fold(&fold)?;
+            on_fold(&fold, self)?;
         }
+        Ok(())
     }
 
     fn count_dots(&self) -> usize {
         self.positions.len()
     }
+
+    /// Draw the paper as a plain PBM image, with a black pixel for each dot.
+    fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width, self.height);
+        for y in 0..self.height {
+            let row = (0..self.width)
+                .map(|x| {
+                    if self.positions.contains(Pos(x, y)) {
+                        '1'
+                    } else {
+                        '0'
+                    }
+                })
+                .collect::<Vec<_>>();
+            // lines of plain PBM files shouldn't be longer than 70 characters
+            for chunk in row.chunks(70) {
+                out.extend(chunk);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Read the letters spelled by the dots, in the font used by the puzzle.
+    fn read(&self) -> Result<String> {
+        ensure!(!self.positions.is_empty(), "No dots to read");
+        let width = self.positions.iter().map(|p| p.0).max().unwrap() + 1;
+        let height = self.positions.iter().map(|p| p.1).max().unwrap() + 1;
+        ensure!(
+            height <= GLYPH_HEIGHT,
+            "The dots don't fit on a single line of text"
+        );
+
+        (0..width.div_ceil(GLYPH_SPACING))
+            .map(|i| {
+                let left = i * GLYPH_SPACING;
+                ensure!(
+                    (left + GLYPH_WIDTH..left + GLYPH_SPACING)
+                        .all(|x| (0..GLYPH_HEIGHT).all(|y| !self.positions.contains(Pos(x, y)))),
+                    "Letter {} is too wide",
+                    i + 1
+                );
+                GLYPHS
+                    .iter()
+                    .find(|(_, rows)| {
+                        rows.iter().zip(0..).all(|(row, y)| {
+                            row.bytes()
+                                .zip(left..)
+                                .all(|(c, x)| (c == b'#') == self.positions.contains(Pos(x, y)))
+                        })
+                    })
+                    .map(|(letter, _)| *letter)
+                    .with_context(|| format!("Unknown letter {}", i + 1))
+            })
+            .collect()
+    }
 }
 
+const GLYPH_WIDTH: u32 = 4;
+const GLYPH_HEIGHT: u32 = 6;
+/// Letters are separated by an empty column.
+const GLYPH_SPACING: u32 = GLYPH_WIDTH + 1;
+
+/// The letters that can appear in the puzzle answers.
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT as usize]); 16] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
 impl Display for Paper {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let width = self.positions.iter().map(|p| p.0).max().unwrap();
-        let height = self.positions.iter().map(|p| p.1).max().unwrap();
-        for y in 0..=height {
-            for x in 0..=width {
-                if self.positions.contains(&Pos(x, y)) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.positions.contains(Pos(x, y)) {
                     write!(f, "#")?;
                 } else {
                     write!(f, " ")?;
@@ -94,6 +364,297 @@ impl Display for Paper {
     }
 }
 
+impl FromStr for Paper {
+    type Err = anyhow::Error;
+
+    /// The size of the paper isn't part of the input: it is assumed to be just large enough for
+    /// the dots, and for the first fold along each axis to be in the middle of the paper, as in
+    /// the puzzle.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut dots = vec![];
+        let mut folds = vec![];
+        let mut parsing_positions = true;
+        for line in s.lines() {
+            if line.is_empty() {
+                parsing_positions = false;
+                continue;
+            }
+
+            if parsing_positions {
+                dots.push(Pos::from_str(line)?);
+            } else {
+                // Parse instruction
+                folds.push(Fold::from_str(line)?);
+            }
+        }
+
+        let first_fold = |x_axis: bool| {
+            let fold = folds
+                .iter()
+                .find(|fold| matches!(fold, Fold::X(_)) == x_axis);
+            match fold {
+                Some(Fold::X(n) | Fold::Y(n)) => n
+                    .checked_mul(2)
+                    .and_then(|size| size.checked_add(1))
+                    .with_context(|| format!("{} needs too large a paper", fold.unwrap())),
+                None => Ok(0),
+            }
+        };
+        let mut width = first_fold(true)?;
+        let mut height = first_fold(false)?;
+        for p in &dots {
+            let too_large = || format!("Dot {},{} needs too large a paper", p.0, p.1);
+            width = width.max(p.0.checked_add(1).with_context(too_large)?);
+            height = height.max(p.1.checked_add(1).with_context(too_large)?);
+        }
+
+        Paper::new(width, height, dots, folds)
+    }
+}
+
+/// Above this density, the dots are stored as a bitmap rather than a set. A set needs about 16
+/// bytes per dot, so the bitmap is smaller from there.
+const DENSE_BITS_PER_DOT: u64 = 128;
+
+/// The dots on a paper.
+#[derive(Debug)]
+enum Dots {
+    Sparse(HashSet<Pos>),
+    Dense(Bitmap),
+}
+
+impl Default for Dots {
+    fn default() -> Self {
+        Dots::Sparse(HashSet::new())
+    }
+}
+
+impl Dots {
+    /// Store `positions` in the way that suits their density.
+    fn new(width: u32, height: u32, positions: impl IntoIterator<Item = Pos>) -> Self {
+        Dots::Sparse(positions.into_iter().collect()).with_density(width, height)
+    }
+
+    fn is_dense(width: u32, height: u32, count: usize) -> bool {
+        width as u64 * height as u64 <= count as u64 * DENSE_BITS_PER_DOT
+    }
+
+    /// Switch to a bitmap or a set if the other one is better suited to the number of dots on a
+    /// `width` x `height` paper.
+    fn with_density(self, width: u32, height: u32) -> Self {
+        match self {
+            Dots::Sparse(set) if Self::is_dense(width, height, set.len()) => {
+                let mut bitmap = Bitmap::new(width, height);
+                for p in set {
+                    bitmap.insert(p);
+                }
+                Dots::Dense(bitmap)
+            }
+            Dots::Dense(bitmap) if !Self::is_dense(width, height, bitmap.len()) => {
+                Dots::Sparse(bitmap.iter().collect())
+            }
+            dots => dots,
+        }
+    }
+
+    fn contains(&self, p: Pos) -> bool {
+        match self {
+            Dots::Sparse(set) => set.contains(&p),
+            Dots::Dense(bitmap) => bitmap.contains(p),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Dots::Sparse(set) => set.len(),
+            Dots::Dense(bitmap) => bitmap.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Pos> + '_ {
+        match self {
+            Dots::Sparse(set) => Either::Left(set.iter().copied()),
+            Dots::Dense(bitmap) => Either::Right(bitmap.iter()),
+        }
+    }
+
+    /// The dots after `fold`, once the paper is `folded` dots wide (or high).
+    fn fold(&self, fold: &Fold, folded: u32) -> Result<Self> {
+        let on_line = match self {
+            Dots::Sparse(set) => set.iter().find(|p| fold.crosses(p)).copied(),
+            Dots::Dense(bitmap) => bitmap.on_line(fold),
+        };
+        if let Some(p) = on_line {
+            bail!("Dot {},{} is on the fold line", p.0, p.1);
+        }
+        Ok(match self {
+            Dots::Sparse(set) => Dots::Sparse(set.iter().map(|p| fold.mirror(p, folded)).collect()),
+            Dots::Dense(bitmap) => Dots::Dense(bitmap.fold(fold, folded)),
+        })
+    }
+}
+
+/// A grid of bits, one row after the other, with each row starting on a new word.
+#[derive(Debug)]
+struct Bitmap {
+    width: u32,
+    height: u32,
+    /// Number of words in a row.
+    stride: usize,
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    fn new(width: u32, height: u32) -> Self {
+        let stride = (width as usize).div_ceil(64);
+        Self {
+            width,
+            height,
+            stride,
+            words: vec![0; stride * height as usize],
+        }
+    }
+
+    fn index(&self, p: Pos) -> (usize, u64) {
+        let word = p.1 as usize * self.stride + p.0 as usize / 64;
+        (word, 1 << (p.0 % 64))
+    }
+
+    fn insert(&mut self, p: Pos) {
+        let (word, bit) = self.index(p);
+        self.words[word] |= bit;
+    }
+
+    fn contains(&self, p: Pos) -> bool {
+        if p.0 >= self.width || p.1 >= self.height {
+            return false;
+        }
+        let (word, bit) = self.index(p);
+        self.words[word] & bit != 0
+    }
+
+    fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Pos> + '_ {
+        self.words.iter().enumerate().flat_map(move |(i, word)| {
+            let y = (i / self.stride) as u32;
+            let left = (i % self.stride) as u32 * 64;
+            let mut word = *word;
+            from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let x = left + word.trailing_zeros();
+                word &= word - 1;
+                Some(Pos(x, y))
+            })
+        })
+    }
+
+    /// A dot on the line of `fold`, if there is one.
+    fn on_line(&self, fold: &Fold) -> Option<Pos> {
+        match *fold {
+            Fold::X(n) => (0..self.height)
+                .map(|y| Pos(n, y))
+                .find(|p| self.contains(*p)),
+            Fold::Y(n) => (0..self.width)
+                .map(|x| Pos(x, n))
+                .find(|p| self.contains(*p)),
+        }
+    }
+
+    /// Fold the bitmap. Horizontal folds merge whole rows together, vertical folds merge each row
+    /// with its own mirrored right half. There must not be any dot on the fold line.
+    fn fold(&self, fold: &Fold, folded: u32) -> Self {
+        match fold {
+            Fold::Y(n) => {
+                let mut bitmap = Bitmap::new(self.width, folded);
+                for y in (0..self.height).filter(|y| y != n) {
+                    let row = y as usize * self.stride;
+                    let folded_row = fold_coordinate(y, *n, folded) as usize * self.stride;
+                    for i in 0..self.stride {
+                        bitmap.words[folded_row + i] |= self.words[row + i];
+                    }
+                }
+                bitmap
+            }
+            Fold::X(n) => {
+                let mut bitmap = Bitmap::new(folded, self.height);
+                if bitmap.stride == 0 {
+                    return bitmap;
+                }
+                let (n, width, folded) = (*n as usize, self.width as usize, folded as usize);
+                let rows = self.words.chunks(self.stride);
+                for (row, folded_row) in rows.zip(bitmap.words.chunks_mut(bitmap.stride)) {
+                    // the left part is shifted to the right if the right part is wider
+                    or_bits(folded_row, folded - n, row, 0, n, false);
+                    or_bits(
+                        folded_row,
+                        folded + n + 1 - width,
+                        row,
+                        n + 1,
+                        width - n - 1,
+                        true,
+                    );
+                }
+                bitmap
+            }
+        }
+    }
+}
+
+/// OR the `len` bits of `src` starting at bit `src_start` into `dst`, starting at bit `dst_start`.
+/// If `mirror` is set, the bits are copied in reverse order.
+fn or_bits(
+    dst: &mut [u64],
+    dst_start: usize,
+    src: &[u64],
+    src_start: usize,
+    len: usize,
+    mirror: bool,
+) {
+    if len == 0 {
+        return;
+    }
+    let end = src_start + len;
+    let (first, last) = (src_start / 64, (end - 1) / 64);
+    for (i, word) in src.iter().enumerate().take(last + 1).skip(first) {
+        let mut word = *word;
+        if word == 0 {
+            continue;
+        }
+        if i == first {
+            word &= !0 << (src_start % 64);
+        }
+        if i == last && !end.is_multiple_of(64) {
+            word &= (1 << (end % 64)) - 1;
+        }
+        // Where bit 0 of the word goes. Bits that would go before the start of `dst` have been
+        // masked out, and so have the ones that would go past its end.
+        let at = if mirror {
+            word = word.reverse_bits();
+            (dst_start + end) as isize - (i as isize + 1) * 64
+        } else {
+            (i * 64 + dst_start) as isize - src_start as isize
+        };
+        let (index, shift) = (at.div_euclid(64), at.rem_euclid(64) as u32);
+        if index >= 0 {
+            dst[index as usize] |= word << shift;
+        }
+        if shift > 0 {
+            if let Some(next) = dst.get_mut((index + 1) as usize) {
+                *next |= word >> (64 - shift);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Eq, PartialEq, Copy, Hash)]
 pub struct Pos(u32, u32);
 
@@ -115,6 +676,7 @@ enum Fold {
 }
 
 impl Fold {
+    #[cfg(test)]
     fn affects(&self, p: &Pos) -> bool {
         match self {
             Fold::X(n) => p.0 > *n,
@@ -122,10 +684,37 @@ impl Fold {
         }
     }
 
-    fn mirror(&self, p: &Pos) -> Pos {
+    fn crosses(&self, p: &Pos) -> bool {
         match self {
-            Fold::X(n) => Pos(2 * *n - p.0, p.1),
-            Fold::Y(n) => Pos(p.0, 2 * *n - p.1),
+            Fold::X(n) => p.0 == *n,
+            Fold::Y(n) => p.1 == *n,
+        }
+    }
+
+    /// Position of `p` after the fold, when the folded paper is `folded` dots wide (or high).
+    fn mirror(&self, p: &Pos, folded: u32) -> Pos {
+        match self {
+            Fold::X(n) => Pos(fold_coordinate(p.0, *n, folded), p.1),
+            Fold::Y(n) => Pos(p.0, fold_coordinate(p.1, *n, folded)),
+        }
+    }
+}
+
+/// Where coordinate `c` ends up after folding along `line`, when the folded paper is `folded` dots
+/// wide (or high).
+fn fold_coordinate(c: u32, line: u32, folded: u32) -> u32 {
+    if c > line {
+        folded + line - c
+    } else {
+        folded - line + c
+    }
+}
+
+impl Display for Fold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fold::X(n) => write!(f, "fold along x={}", n),
+            Fold::Y(n) => write!(f, "fold along y={}", n),
         }
     }
 }
@@ -149,3 +738,373 @@ impl FromStr for Fold {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    /// A paper with a dot for each `#` in `art`.
+    fn paper(art: &str) -> Paper {
+        let dots = art
+            .lines()
+            .zip(0..)
+            .flat_map(|(line, y)| {
+                line.bytes()
+                    .zip(0..)
+                    .filter(|(c, _)| *c == b'#')
+                    .map(move |(_, x)| Pos(x, y))
+            })
+            .collect();
+        let width = art.lines().map(|line| line.len() as u32).max().unwrap_or(0);
+        let height = art.lines().count() as u32;
+        Paper::new(width, height, dots, vec![]).unwrap()
+    }
+
+    const EXAMPLE: &str = "6,10
+0,14
+9,10
+0,3
+10,4
+4,11
+6,0
+6,12
+4,1
+0,13
+10,12
+3,4
+3,0
+8,4
+1,10
+2,14
+8,10
+9,0
+
+fold along y=7
+fold along x=5";
+
+    #[test]
+    fn test_example() {
+        let mut paper = Paper::from_str(EXAMPLE).unwrap();
+        assert_eq!((11, 15), (paper.width, paper.height));
+
+        paper.fold_once().unwrap();
+        assert_eq!(17, paper.count_dots());
+        assert_eq!((11, 7), (paper.width, paper.height));
+
+        paper.fold_all().unwrap();
+        assert_eq!(16, paper.count_dots());
+        assert_eq!(
+            "#####\n#   #\n#   #\n#   #\n#####\n     \n     \n",
+            paper.to_string()
+        );
+    }
+
+    #[test]
+    fn test_fold_history() {
+        let mut paper = Paper::from_str(EXAMPLE).unwrap();
+        let mut history = vec![];
+        paper
+            .fold_all_with(|fold, paper| {
+                history.push((
+                    fold.to_string(),
+                    paper.count_dots(),
+                    paper.width,
+                    paper.height,
+                ));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("fold along y=7".to_string(), 17, 11, 7),
+                ("fold along x=5".to_string(), 16, 5, 7)
+            ],
+            history
+        );
+        assert!(paper.folds.is_empty());
+
+        let mut paper = Paper::from_str(EXAMPLE).unwrap();
+        let error = paper.fold_all_with(|_, _| bail!("stop")).unwrap_err();
+        assert_eq!("stop", error.to_string());
+        assert_eq!(1, paper.folds.len());
+    }
+
+    #[test]
+    fn test_pbm() {
+        assert_eq!("P1\n3 2\n100\n011\n", paper("#..\n.##").to_pbm());
+
+        let long = paper(&"#".repeat(75)).to_pbm();
+        assert_eq!(
+            format!("P1\n75 1\n{}\n{}\n", "1".repeat(70), "1".repeat(5)),
+            long
+        );
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for (message, folds) in [("RCPLAKHL", 12), ("HELLO", 0), ("ABC", 1), ("ZUSGJ", 8)] {
+            let paper = Paper::unfolded(message, folds, &mut rng).unwrap();
+            assert_eq!(folds, paper.folds.len());
+            if let Some(fold) = paper.folds.front() {
+                // the dots are spread on both sides of the first fold
+                assert!(paper.positions.iter().any(|p| fold.affects(&p)));
+                assert!(paper.positions.iter().any(|p| !fold.affects(&p)));
+            }
+
+            let mut paper = Paper::from_str(&paper.to_input()).unwrap();
+            paper.fold_all().unwrap();
+            assert_eq!(message, paper.read().unwrap());
+            assert_eq!(
+                ((message.len() * 5 - 1) as u32, 6),
+                (paper.width, paper.height)
+            );
+        }
+
+        assert!(Paper::unfolded("hello", 1, &mut rng).is_err());
+        let mut paper = Paper::with_message("A").unwrap();
+        assert!(paper.unfold(Fold::X(3), &mut rng).is_err());
+    }
+
+    /// Store the dots of `paper` as a bitmap if `dense` is set, or as a set.
+    fn force_layout(paper: &mut Paper, dense: bool) {
+        let positions = paper.positions.iter().collect::<HashSet<_>>();
+        paper.positions = if dense {
+            let mut bitmap = Bitmap::new(paper.width, paper.height);
+            for p in positions {
+                bitmap.insert(p);
+            }
+            Dots::Dense(bitmap)
+        } else {
+            Dots::Sparse(positions)
+        };
+    }
+
+    #[test]
+    fn test_layouts() {
+        let mut rng = StdRng::seed_from_u64(47);
+        for folds in [0, 1, 5, 10] {
+            let input = Paper::unfolded("GRAPHS", folds, &mut rng)
+                .unwrap()
+                .to_input();
+            let mut sparse = Paper::from_str(&input).unwrap();
+            force_layout(&mut sparse, false);
+            let mut dense = Paper::from_str(&input).unwrap();
+            force_layout(&mut dense, true);
+            assert!(matches!(dense.positions, Dots::Dense(_)));
+
+            while let Some(fold) = sparse.folds.pop_front() {
+                dense.folds.pop_front();
+                sparse.fold_keeping_layout(&fold).unwrap();
+                dense.fold_keeping_layout(&fold).unwrap();
+                assert_eq!((sparse.width, sparse.height), (dense.width, dense.height));
+                assert_eq!(sparse.count_dots(), dense.count_dots());
+                assert_eq!(
+                    sparse.positions.iter().collect::<HashSet<_>>(),
+                    dense.positions.iter().collect::<HashSet<_>>()
+                );
+            }
+            assert!(matches!(sparse.positions, Dots::Sparse(_)));
+            assert_eq!("GRAPHS", dense.read().unwrap());
+        }
+
+        // dots on the fold line are found in both layouts
+        for dense in [false, true] {
+            let mut dots = paper("#.#.#\n.....");
+            force_layout(&mut dots, dense);
+            assert!(dots.fold(&Fold::X(2)).is_err());
+            let mut dots = paper("#.#.#\n.....\n..#..");
+            force_layout(&mut dots, dense);
+            assert!(dots.fold(&Fold::Y(1)).is_ok());
+            assert_eq!("#.#.#\n".replace('.', " "), dots.to_string());
+        }
+    }
+
+    #[test]
+    fn test_density() {
+        // large papers start sparse, and become dense as they're folded
+        let mut rng = StdRng::seed_from_u64(13);
+        let mut paper = Paper::unfolded("HELLO", 12, &mut rng).unwrap();
+        assert!(matches!(paper.positions, Dots::Sparse(_)));
+        paper.fold_all().unwrap();
+        assert!(matches!(paper.positions, Dots::Dense(_)));
+
+        // and back
+        let dots = Dots::new(2, 2, [Pos(0, 0)]).with_density(1000, 1000);
+        assert!(matches!(dots, Dots::Sparse(_)));
+
+        let mut bitmap = Bitmap::new(130, 2);
+        for p in [Pos(0, 0), Pos(63, 0), Pos(64, 0), Pos(129, 1)] {
+            bitmap.insert(p);
+        }
+        assert_eq!(4, bitmap.len());
+        assert_eq!(
+            vec![Pos(0, 0), Pos(63, 0), Pos(64, 0), Pos(129, 1)],
+            bitmap.iter().collect::<Vec<_>>()
+        );
+        assert!(bitmap.contains(Pos(129, 1)) && !bitmap.contains(Pos(129, 0)));
+        assert!(!bitmap.contains(Pos(130, 1)));
+    }
+
+    #[test]
+    fn test_bitmap_fold() {
+        // vertical folds anywhere on the paper, across word boundaries
+        let mut rng = StdRng::seed_from_u64(47);
+        for _ in 0..500 {
+            let width = rng.gen_range(2..300);
+            let line = rng.gen_range(0..width);
+            let (fold, folded) = (Fold::X(line), line.max(width - 1 - line));
+            let mut bitmap = Bitmap::new(width, 3);
+            let mut expected = HashSet::new();
+            for _ in 0..rng.gen_range(0..2 * width) {
+                let p = Pos(rng.gen_range(0..width), rng.gen_range(0..3));
+                if !fold.crosses(&p) {
+                    bitmap.insert(p);
+                    expected.insert(fold.mirror(&p, folded));
+                }
+            }
+            let bitmap = bitmap.fold(&fold, folded);
+            assert_eq!(
+                expected,
+                bitmap.iter().collect::<HashSet<_>>(),
+                "{} on a paper {} wide",
+                fold,
+                width
+            );
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_fold() {
+        let mut rng = StdRng::seed_from_u64(2021);
+        let mut inputs = vec![];
+        // sparse papers, as generated from a message
+        for folds in [12, 16, 20] {
+            let paper = Paper::unfolded("RCPLAKHL", folds, &mut rng).unwrap();
+            inputs.push((format!("RCPLAKHL, {} folds", folds), paper));
+        }
+        // dense papers: a square almost full of dots, unfolded
+        for folds in [6, 10] {
+            let mut dots = vec![];
+            for p in (0..127).flat_map(|y| (0..127).map(move |x| Pos(x, y))) {
+                if rng.gen_bool(0.9) {
+                    dots.push(p);
+                }
+            }
+            let mut paper = Paper::new(127, 127, dots, vec![]).unwrap();
+            for i in 0..folds {
+                let fold = if i % 2 == 0 {
+                    Fold::X(paper.width)
+                } else {
+                    Fold::Y(paper.height)
+                };
+                paper.unfold(fold, &mut rng).unwrap();
+            }
+            inputs.push((format!("square, {} folds", folds), paper));
+        }
+
+        for (name, paper) in inputs {
+            let input = paper.to_input();
+            let mut results = vec![];
+            for layout in ["sparse", "dense", "auto"] {
+                let mut paper = Paper::from_str(&input).unwrap();
+                let dots = paper.count_dots();
+                let size = (paper.width, paper.height);
+                if layout != "auto" {
+                    force_layout(&mut paper, layout == "dense");
+                }
+                let start = std::time::Instant::now();
+                while let Some(fold) = paper.folds.pop_front() {
+                    if layout == "auto" {
+                        paper.fold(&fold).unwrap();
+                    } else {
+                        paper.fold_keeping_layout(&fold).unwrap();
+                    }
+                }
+                let elapsed = start.elapsed();
+                results.push(paper.positions.iter().collect::<HashSet<_>>());
+                println!(
+                    "{}: {} dots on {}x{}, {}: {:?}",
+                    name, dots, size.0, size.1, layout, elapsed
+                );
+            }
+            assert!(results.iter().all_equal());
+        }
+    }
+
+    #[test]
+    fn test_off_centre_folds() {
+        // the right part is larger, so it ends up on the left
+        let mut wide = paper("#..#.....#");
+        wide.fold(&Fold::X(2)).unwrap();
+        assert_eq!(7, wide.width);
+        assert_eq!(
+            HashSet::from([Pos(5, 0), Pos(6, 0), Pos(0, 0)]),
+            wide.positions.iter().collect()
+        );
+
+        // the bottom part is smaller
+        let mut tall = paper("#\n.\n.\n.\n.\n#");
+        tall.fold(&Fold::Y(3)).unwrap();
+        assert_eq!(3, tall.height);
+        assert_eq!(
+            HashSet::from([Pos(0, 0), Pos(0, 1)]),
+            tall.positions.iter().collect()
+        );
+        assert_eq!("#\n#\n \n", tall.to_string());
+    }
+
+    #[test]
+    fn test_invalid_folds() {
+        let mut dots = paper("#.#.#");
+        assert!(dots.fold(&Fold::X(2)).is_err());
+        assert!(dots.fold(&Fold::X(5)).is_err());
+        assert!(dots.fold(&Fold::Y(0)).is_err());
+        assert!(Paper::new(2, 2, vec![Pos(2, 0)], vec![]).is_err());
+
+        // the size of the paper must fit in a u32
+        let err = Paper::from_str("1,1\n\nfold along x=3000000000").unwrap_err();
+        assert!(err.to_string().contains("too large"), "{}", err);
+        assert!(Paper::from_str("4294967295,1\n\nfold along y=1").is_err());
+        let mut paper = Paper::from_str("1,1\n\nfold along x=2000000000").unwrap();
+        paper.fold_all().unwrap();
+        assert_eq!(1, paper.count_dots());
+    }
+
+    #[test]
+    fn test_read() {
+        let art = "\
+###   ##  ###  #     ##  #  # #  # #
+#  # #  # #  # #    #  # # #  #  # #
+#  # #    #  # #    #  # ##   #### #
+###  #    ###  #    #### # #  #  # #
+# #  #  # #    #    #  # # #  #  # #
+#  #  ##  #    #### #  # #  # #  # ####";
+        assert_eq!("RCPLAKHL", paper(art).read().unwrap());
+
+        for (letter, rows) in GLYPHS {
+            assert_eq!(letter.to_string(), paper(&rows.join("\n")).read().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_read_errors() {
+        // empty paper
+        assert!(paper("").read().is_err());
+        assert!(paper("....").read().is_err());
+        // too tall
+        assert!(paper("#\n#\n#\n#\n#\n#\n#").read().is_err());
+        // unknown letter
+        assert!(paper("####\n####\n####\n####\n####\n####").read().is_err());
+        // no space between letters
+        assert!(
+            paper("#..##..#\n#..##..#\n####.###\n#..##..#\n#..##..#\n#..##..#")
+                .read()
+                .is_err()
+        );
+    }
+}
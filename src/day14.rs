@@ -48,6 +48,7 @@ impl Counters {
         self.0[(c - b'A') as usize] += 1;
     }
 
+    #[cfg(test)]
     fn get_count(&self, c: u8) -> u64 {
         self.0[(c - b'A') as usize]
     }
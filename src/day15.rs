@@ -7,7 +7,6 @@ pub fn run() -> Result<()> {
 
     let costs = content
         .lines()
-        .into_iter()
         .flat_map(|line| line.as_bytes().iter().map(|c| c - b'0'))
         .collect::<Vec<_>>();
 
@@ -1,96 +1,189 @@
-use std::ops::Deref;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, Bytes, Read, Write},
+    ops::Range,
+};
 
-use anyhow::{Context, Result};
-use bitvec::prelude::*;
+use anyhow::{bail, ensure, Context, Result};
 
 pub fn run() -> Result<()> {
-    let content = std::fs::read_to_string("inputs/day16.txt")?;
+    let file = File::open("inputs/day16.txt")?;
 
-    let mut bv = parse_hex_data(&content)?;
-    let packet = parse_packet(&mut bv);
+    let packet = PacketStream::new(file)
+        .next()
+        .context("Empty transmission")??;
 
     let sum = packet.version_sum();
     println!("day16 part1 = {}", sum);
 
-    let eval = packet.eval();
+    let eval = packet.eval()?;
     println!("day16 part2 = {}", eval);
     Ok(())
 }
 
-fn parse_hex_data(data: &str) -> Result<BitBuffer> {
-    let bytes = data
-        .trim()
-        .as_bytes()
-        .chunks(2)
-        .map(|src| {
-            u8::from_str_radix(String::from_utf8_lossy(src).as_ref(), 16).context("parsing error")
-        })
-        .collect::<Result<Vec<_>>>()?;
-    let bv = BitVec::<Msb0, _>::from_slice(&bytes).context("Failed to build BitVec")?;
+/// Print every field of the given transmissions bit by bit, indented by nesting depth. Reads the
+/// transmissions from stdin if none is given.
+pub fn inspect(hex: Option<&str>) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match hex {
+        Some(hex) => dump(hex.as_bytes(), &mut out),
+        None => dump(io::stdin().lock(), &mut out),
+    }
+}
+
+fn dump<R: Read, W: Write>(input: R, out: &mut W) -> Result<()> {
+    let mut reader = HexReader::new(input);
+    let mut count = 0;
+    while reader.start_transmission()? {
+        count += 1;
+        writeln!(out, "transmission {}", count)?;
+
+        let mut annotator = Annotator::new(&mut reader);
+        let result = parse_packet(&mut annotator);
+        for annotation in &annotator.annotations {
+            writeln!(out, "{}", annotation)?;
+        }
+        let packet = match result {
+            Ok(packet) => packet,
+            Err(e) => {
+                let position = annotator.position();
+                return Err(e.context(format!("Invalid packet at bit {}", position)));
+            }
+        };
+
+        let position = reader.position();
+        let padding = reader.skip_padding()?;
+        if padding > 0 {
+            writeln!(
+                out,
+                "{:>6}  {:<16}{}",
+                position,
+                "padding",
+                "0".repeat(padding)
+            )?;
+        }
+        writeln!(
+            out,
+            "{} packets ({} literals), depth {}, {} bits",
+            packet.node_count(),
+            packet.find_by_type(4).count(),
+            packet.depth(),
+            packet.span.len()
+        )?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Print a simplified (but equivalent) encoding of the given transmissions. Reads the transmissions
+/// from stdin if none is given.
+pub fn simplify(hex: Option<&str>) -> Result<()> {
+    let packets: Box<dyn Iterator<Item = Result<Packet>>> = match hex {
+        Some(hex) => Box::new(PacketStream::new(hex.as_bytes())),
+        None => Box::new(PacketStream::new(io::stdin().lock())),
+    };
+    for packet in packets {
+        println!("{}", packet?.simplify().to_hex()?);
+    }
+
+    Ok(())
+}
 
-    Ok(BitBuffer(bv))
+#[cfg(test)]
+fn parse_hex_data(data: &str) -> Result<HexReader<&[u8]>> {
+    let mut reader = HexReader::new(data.as_bytes());
+    ensure!(reader.start_transmission()?, "Empty transmission");
+
+    Ok(reader)
+}
+
+fn parse_header<S: BitSource>(data: &mut S) -> Result<Header> {
+    let version = data.read_field(Field::Version, 3)? as u8;
+    let type_id = data.read_field(Field::TypeId, 3)? as u8;
+
+    Ok(Header::new(version, type_id))
 }
 
-fn parse_header(data: &mut BitBuffer) -> Header {
-    let header = data.take(6);
-    let version: u8 = header[0..3].load_be();
-    let type_id: u8 = header[3..6].load_be();
+/// How deep packets can be nested, so that a hostile transmission can't overflow the stack.
+const MAX_DEPTH: usize = 256;
 
-    Header::new(version, type_id)
+fn parse_packet<S: BitSource>(data: &mut S) -> Result<Packet> {
+    parse_nested_packet(data, 1)
 }
 
-fn parse_packet(data: &mut BitBuffer) -> Packet {
-    let header = parse_header(data);
+fn parse_nested_packet<S: BitSource>(data: &mut S, depth: usize) -> Result<Packet> {
+    ensure!(
+        depth <= MAX_DEPTH,
+        "Packets are nested more than {} levels deep",
+        MAX_DEPTH
+    );
+    data.enter_packet();
+    let start = data.position();
+    let header = parse_header(data)?;
 
     let payload = match header.type_id {
         4 => {
-            let mut literal = BitVec::<Msb0, u8>::new();
+            let mut literal = 0u64;
             loop {
-                let chunk = data.take(5);
-                literal.extend_from_bitslice(&chunk[1..]);
-                if !chunk[0] {
+                let chunk = data.read_field(Field::LiteralGroup, 5)?;
+                ensure!(literal >> 60 == 0, "Literal value overflows 64 bits");
+                literal = (literal << 4) | (chunk & 0b1111);
+                if chunk & 0b10000 == 0 {
                     break;
                 }
             }
-            PacketData::Literal(literal[..].load_be::<u64>())
+            PacketData::Literal(literal)
         }
         _n => {
-            let length_type_id = data.take(1)[0];
+            let length_type_id = data.read_field(Field::LengthTypeId, 1)?;
             let mut packets = Vec::new();
-            if length_type_id {
-                let num_packets_bits = data.take(11);
-                let num_packets: usize = num_packets_bits.load_be();
+            if length_type_id == 1 {
+                let num_packets = data.read_field(Field::PacketCount, 11)?;
                 for _ in 0..num_packets {
-                    let packet = parse_packet(data);
+                    let packet = parse_nested_packet(data, depth + 1)?;
                     packets.push(packet);
                 }
             } else {
-                let len_bits = data.take(15);
-                let len: usize = len_bits.load_be();
-                let mut packets_data = data.take(len);
-                packets.append(&mut parse_packets(&mut packets_data));
+                let len = data.read_field(Field::TotalLength, 15)? as usize;
+                let end = data.position() + len;
+                // sub-packets are read from the same stream until we've consumed exactly `len` bits
+                while data.position() < end {
+                    let packet = parse_nested_packet(data, depth + 1)?;
+                    packets.push(packet);
+                }
+                ensure!(
+                    data.position() == end,
+                    "Sub-packets overrun their declared length of {} bits",
+                    len
+                );
+            }
+            if header.type_id >= 5 {
+                ensure!(
+                    packets.len() == 2,
+                    "'{}' operator with {} sub-packets instead of 2",
+                    type_name(header.type_id),
+                    packets.len()
+                );
+            } else {
+                ensure!(
+                    !packets.is_empty(),
+                    "'{}' operator without sub-packets",
+                    type_name(header.type_id)
+                );
             }
             PacketData::Operator(packets)
         }
     };
+    data.exit_packet();
 
-    Packet {
+    Ok(Packet {
         header,
         data: payload,
-    }
-}
-
-fn parse_packets(data: &mut BitBuffer) -> Vec<Packet> {
-    let mut packets = Vec::new();
-    loop {
-        let packet = parse_packet(data);
-        packets.push(packet);
-        if data.is_empty() {
-            break;
-        }
-    }
-
-    packets
+        span: start..data.position(),
+    })
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -109,32 +202,328 @@ impl Header {
 struct Packet {
     header: Header,
     data: PacketData,
+    /// The bits this packet occupies in its transmission.
+    span: Range<usize>,
 }
 
 impl Packet {
     fn version_sum(&self) -> u64 {
+        self.pre_order()
+            .map(|visit| visit.packet.header.version as u64)
+            .sum()
+    }
+
+    /// Value of the expression this packet represents. Fails if a sum or a product doesn't fit in
+    /// 64 bits.
+    fn eval(&self) -> Result<u64> {
+        // Sub-packets are always visited before their parent, so by the time we get to an operator
+        // its operands are the last values on the stack.
+        let mut values: Vec<u64> = Vec::new();
+        for visit in self.post_order() {
+            let value = match visit.packet.data {
+                PacketData::Literal(n) => n,
+                PacketData::Operator(ref packets) => {
+                    let operands = values.split_off(values.len() - packets.len());
+                    let type_id = visit.packet.header.type_id;
+                    let value = match type_id {
+                        0..=3 => combine(type_id, &operands),
+                        5 => Some((operands[0] > operands[1]) as u64),
+                        6 => Some((operands[0] < operands[1]) as u64),
+                        7 => Some((operands[0] == operands[1]) as u64),
+                        _ => panic!("Unknown op code"),
+                    };
+                    value.with_context(|| {
+                        format!(
+                            "The {} at bit {} overflows 64 bits",
+                            type_name(type_id),
+                            visit.packet.span.start
+                        )
+                    })?
+                }
+            };
+            values.push(value);
+        }
+
+        Ok(values[0])
+    }
+
+    /// Sub-packets of this packet (empty for literals).
+    fn children(&self) -> &[Packet] {
         match self.data {
-            PacketData::Literal(_) => self.header.version as u64,
-            PacketData::Operator(ref packets) => {
-                let sum: u64 = packets.iter().map(|p| p.version_sum()).sum();
-                self.header.version as u64 + sum
+            PacketData::Literal(_) => &[],
+            PacketData::Operator(ref packets) => packets,
+        }
+    }
+
+    /// Iterate over this packet and all its sub-packets, parents first.
+    fn pre_order(&self) -> PreOrder<'_> {
+        PreOrder {
+            stack: vec![(self, 0)],
+        }
+    }
+
+    /// Iterate over this packet and all its sub-packets, children first.
+    fn post_order(&self) -> PostOrder<'_> {
+        PostOrder {
+            stack: vec![(self, 0, false)],
+        }
+    }
+
+    /// Number of levels in the packet tree (1 for a lone literal).
+    fn depth(&self) -> usize {
+        self.pre_order().map(|visit| visit.depth + 1).max().unwrap()
+    }
+
+    /// Number of packets in the tree, including this one.
+    fn node_count(&self) -> usize {
+        self.pre_order().count()
+    }
+
+    /// All the packets in the tree with the given type id, in pre-order.
+    fn find_by_type(&self, type_id: u8) -> impl Iterator<Item = &Packet> {
+        self.pre_order()
+            .map(|visit| visit.packet)
+            .filter(move |packet| packet.header.type_id == type_id)
+    }
+
+    /// Simplify the expression this packet represents without changing its value: nested
+    /// sums/products/minimums/maximums are flattened, their literal operands are combined into a
+    /// single one (unless that overflows), identity elements are removed, products by zero become
+    /// zero, and operators left with a single operand are replaced by that operand.
+    ///
+    /// Rewritten packets keep the span of the packet they replace, and the versions of the
+    /// packets that get flattened or removed are lost.
+    fn simplify(self) -> Packet {
+        let Packet { header, data, span } = self;
+        let packets = match data {
+            PacketData::Literal(_) => return Packet { header, data, span },
+            PacketData::Operator(packets) => packets,
+        };
+        let identity = match identity(header.type_id) {
+            Some(identity) => identity,
+            None => {
+                let packets = packets.into_iter().map(Packet::simplify).collect();
+                return Packet {
+                    header,
+                    data: PacketData::Operator(packets),
+                    span,
+                };
+            }
+        };
+
+        let mut operands = Vec::new();
+        for packet in packets.into_iter().map(Packet::simplify) {
+            match packet.data {
+                // sub-packets have already been flattened, so one level is enough
+                PacketData::Operator(nested) if packet.header.type_id == header.type_id => {
+                    operands.extend(nested)
+                }
+                PacketData::Literal(n) if n == identity => {}
+                _ => operands.push(packet),
             }
         }
+
+        // Combine the literal operands into the first one. The result can't be the identity
+        // element, since that has been removed from the operands.
+        let literals = operands
+            .iter()
+            .filter_map(|p| match p.data {
+                PacketData::Literal(n) => Some(n),
+                PacketData::Operator(_) => None,
+            })
+            .collect::<Vec<_>>();
+        if literals.len() > 1 {
+            if let Some(value) = combine(header.type_id, &literals) {
+                let mut first = true;
+                operands.retain_mut(|p| match p.data {
+                    PacketData::Literal(_) if first => {
+                        first = false;
+                        p.data = PacketData::Literal(value);
+                        true
+                    }
+                    PacketData::Literal(_) => false,
+                    PacketData::Operator(_) => true,
+                });
+            }
+        }
+
+        let literal = |n| Packet {
+            header: Header::new(header.version, 4),
+            data: PacketData::Literal(n),
+            span: span.clone(),
+        };
+        if header.type_id == 1 && operands.iter().any(|p| p.data == PacketData::Literal(0)) {
+            return literal(0);
+        }
+        match operands.len() {
+            0 => literal(identity),
+            1 => operands.pop().unwrap(),
+            _ => Packet {
+                header,
+                data: PacketData::Operator(operands),
+                span,
+            },
+        }
+    }
+
+    /// Encode this packet as a hex transmission. Operators always use the number of sub-packets
+    /// as their length, unless they have too many of them.
+    fn to_hex(&self) -> Result<String> {
+        let mut bits = BitWriter::default();
+        self.encode(&mut bits)?;
+
+        Ok(bits.to_hex())
     }
 
-    fn eval(&self) -> u64 {
+    fn encode(&self, out: &mut BitWriter) -> Result<()> {
+        ensure!(
+            self.header.version < 8,
+            "Invalid version {}",
+            self.header.version
+        );
+        out.write(self.header.version as u64, 3);
+        out.write(self.header.type_id as u64, 3);
         match self.data {
-            PacketData::Literal(n) => n,
-            PacketData::Operator(ref packets) => match self.header.type_id {
-                0 => packets.iter().map(|p| p.eval()).sum(),
-                1 => packets.iter().map(|p| p.eval()).product(),
-                2 => packets.iter().map(|p| p.eval()).min().unwrap(),
-                3 => packets.iter().map(|p| p.eval()).max().unwrap(),
-                5 => (packets[0].eval() > packets[1].eval()) as u64,
-                6 => (packets[0].eval() < packets[1].eval()) as u64,
-                7 => (packets[0].eval() == packets[1].eval()) as u64,
-                _ => panic!("Unknown op code"),
-            },
+            PacketData::Literal(n) => {
+                ensure!(
+                    self.header.type_id == 4,
+                    "Literal with type id {}",
+                    self.header.type_id
+                );
+                let groups = (64 - n.leading_zeros() as usize).div_ceil(4);
+                for i in (0..groups.max(1)).rev() {
+                    let more = (i > 0) as u64;
+                    out.write((more << 4) | ((n >> (4 * i)) & 0b1111), 5);
+                }
+            }
+            PacketData::Operator(ref packets) => {
+                ensure!(
+                    self.header.type_id < 8 && self.header.type_id != 4,
+                    "Operator with type id {}",
+                    self.header.type_id
+                );
+                if packets.len() < 1 << 11 {
+                    out.write(1, 1);
+                    out.write(packets.len() as u64, 11);
+                    for packet in packets {
+                        packet.encode(out)?;
+                    }
+                } else {
+                    let mut sub_packets = BitWriter::default();
+                    for packet in packets {
+                        packet.encode(&mut sub_packets)?;
+                    }
+                    let len = sub_packets.0.len();
+                    ensure!(len < 1 << 15, "Too many sub-packets to encode");
+                    out.write(0, 1);
+                    out.write(len as u64, 15);
+                    out.0.extend(sub_packets.0);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The value that leaves the result of the given operator unchanged, if the operator is a
+/// sum, product, minimum or maximum.
+/// Value of a sum, product, minimum or maximum of `values`, if it fits in 64 bits.
+fn combine(type_id: u8, values: &[u64]) -> Option<u64> {
+    match type_id {
+        0 => values.iter().try_fold(0u64, |acc, n| acc.checked_add(*n)),
+        // a zero operand makes the product fit, whatever the others are
+        1 if values.contains(&0) => Some(0),
+        1 => values.iter().try_fold(1u64, |acc, n| acc.checked_mul(*n)),
+        2 => values.iter().min().copied(),
+        3 => values.iter().max().copied(),
+        _ => None,
+    }
+}
+
+fn identity(type_id: u8) -> Option<u64> {
+    match type_id {
+        0 => Some(0),
+        1 => Some(1),
+        2 => Some(u64::MAX),
+        3 => Some(0),
+        _ => None,
+    }
+}
+
+/// Accumulates the bits of a transmission being encoded.
+#[derive(Debug, Default)]
+struct BitWriter(Vec<bool>);
+
+impl BitWriter {
+    /// Append the lowest `count` bits of `value`, most significant first.
+    fn write(&mut self, value: u64, count: usize) {
+        self.0
+            .extend((0..count).rev().map(|i| (value >> i) & 1 == 1));
+    }
+
+    /// Hex representation of the bits written so far, padded with zeros.
+    fn to_hex(&self) -> String {
+        self.0
+            .chunks(4)
+            .map(|chunk| {
+                let digit = (0..4).fold(0, |d, i| {
+                    (d << 1) | chunk.get(i).copied().unwrap_or(false) as u32
+                });
+                std::char::from_digit(digit, 16)
+                    .unwrap()
+                    .to_ascii_uppercase()
+            })
+            .collect()
+    }
+}
+
+/// A packet reached while traversing a packet tree.
+#[derive(Debug, Clone, Copy)]
+struct Visit<'a> {
+    packet: &'a Packet,
+    /// Nesting level of the packet, starting at 0 for the root of the traversal.
+    depth: usize,
+}
+
+struct PreOrder<'a> {
+    stack: Vec<(&'a Packet, usize)>,
+}
+
+impl<'a> Iterator for PreOrder<'a> {
+    type Item = Visit<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (packet, depth) = self.stack.pop()?;
+        self.stack
+            .extend(packet.children().iter().rev().map(|p| (p, depth + 1)));
+
+        Some(Visit { packet, depth })
+    }
+}
+
+struct PostOrder<'a> {
+    /// Packets left to visit, and whether their children have already been pushed on the stack.
+    stack: Vec<(&'a Packet, usize, bool)>,
+}
+
+impl<'a> Iterator for PostOrder<'a> {
+    type Item = Visit<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (packet, depth, expanded) = self.stack.pop()?;
+            if expanded || packet.children().is_empty() {
+                return Some(Visit { packet, depth });
+            }
+            self.stack.push((packet, depth, true));
+            self.stack.extend(
+                packet
+                    .children()
+                    .iter()
+                    .rev()
+                    .map(|p| (p, depth + 1, false)),
+            );
         }
     }
 }
@@ -145,38 +534,324 @@ enum PacketData {
     Operator(Vec<Packet>),
 }
 
-struct BitBuffer(BitVec<Msb0, u8>);
+/// The fields of a packet, as they appear in the transmission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Version,
+    TypeId,
+    LiteralGroup,
+    LengthTypeId,
+    TotalLength,
+    PacketCount,
+}
+
+impl Field {
+    fn label(&self) -> &'static str {
+        match self {
+            Field::Version => "version",
+            Field::TypeId => "type id",
+            Field::LiteralGroup => "literal group",
+            Field::LengthTypeId => "length type id",
+            Field::TotalLength => "total length",
+            Field::PacketCount => "packet count",
+        }
+    }
+}
+
+fn type_name(type_id: u8) -> &'static str {
+    match type_id {
+        0 => "sum",
+        1 => "product",
+        2 => "minimum",
+        3 => "maximum",
+        4 => "literal",
+        5 => "greater than",
+        6 => "less than",
+        7 => "equal to",
+        _ => "unknown",
+    }
+}
+
+/// Where the packet decoder gets its bits from.
+trait BitSource {
+    /// Read the next `count` bits, making up the given field, as a big-endian number.
+    fn read_field(&mut self, field: Field, count: usize) -> Result<u64>;
 
-impl BitBuffer {
-    fn take(&mut self, count: usize) -> BitBuffer {
-        let mut rest = self.0.split_off(count);
-        std::mem::swap(&mut self.0, &mut rest);
+    /// Number of bits consumed since the start of the transmission.
+    fn position(&self) -> usize;
 
-        Self(rest)
+    /// Called when the decoder starts parsing a (sub-)packet.
+    fn enter_packet(&mut self) {}
+
+    /// Called when the decoder is done parsing a (sub-)packet.
+    fn exit_packet(&mut self) {}
+}
+
+/// A field read by the decoder, and where it was found in the transmission.
+#[derive(Debug)]
+struct Annotation {
+    offset: usize,
+    depth: usize,
+    field: Field,
+    len: usize,
+    value: u64,
+}
+
+impl Display for Annotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let indent = "  ".repeat(self.depth.saturating_sub(1));
+        write!(
+            f,
+            "{:>6}  {}{:<16}{:0len$b}  ",
+            self.offset,
+            indent,
+            self.field.label(),
+            self.value,
+            len = self.len
+        )?;
+        match self.field {
+            Field::TypeId => write!(f, "{} ({})", self.value, type_name(self.value as u8)),
+            Field::LiteralGroup if self.value & 0b10000 == 0 => {
+                write!(f, "{} (last)", self.value & 0b1111)
+            }
+            Field::LiteralGroup => write!(f, "{}", self.value & 0b1111),
+            Field::LengthTypeId if self.value == 0 => write!(f, "0 (length in bits)"),
+            Field::LengthTypeId => write!(f, "1 (number of sub-packets)"),
+            _ => write!(f, "{}", self.value),
+        }
     }
 }
 
-impl Deref for BitBuffer {
-    type Target = BitSlice<Msb0, u8>;
+/// Bit source that records every field read from the wrapped source.
+struct Annotator<'a, S> {
+    inner: &'a mut S,
+    depth: usize,
+    annotations: Vec<Annotation>,
+}
 
-    fn deref(&self) -> &BitSlice<Msb0, u8> {
-        self.0.deref()
+impl<'a, S: BitSource> Annotator<'a, S> {
+    fn new(inner: &'a mut S) -> Self {
+        Self {
+            inner,
+            depth: 0,
+            annotations: Vec::new(),
+        }
+    }
+}
+
+impl<'a, S: BitSource> BitSource for Annotator<'a, S> {
+    fn read_field(&mut self, field: Field, count: usize) -> Result<u64> {
+        let offset = self.inner.position();
+        let value = self.inner.read_field(field, count)?;
+        self.annotations.push(Annotation {
+            offset,
+            depth: self.depth,
+            field,
+            len: count,
+            value,
+        });
+
+        Ok(value)
+    }
+
+    fn position(&self) -> usize {
+        self.inner.position()
+    }
+
+    fn enter_packet(&mut self) {
+        self.depth += 1;
+    }
+
+    fn exit_packet(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+/// Iterator over the top-level packets of a stream of hex-encoded transmissions.
+///
+/// Transmissions are separated by whitespace (typically one per line), and each one holds a single
+/// packet followed by zero padding. Only a few bits of the input are buffered at any time, so
+/// arbitrarily long transmissions can be decoded straight from a file, stdin or a pipe.
+struct PacketStream<R> {
+    reader: HexReader<R>,
+}
+
+impl<R: Read> PacketStream<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: HexReader::new(reader),
+        }
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet>> {
+        if !self.reader.start_transmission()? {
+            return Ok(None);
+        }
+        let packet = parse_packet(&mut self.reader)?;
+        self.reader.skip_padding()?;
+
+        Ok(Some(packet))
+    }
+}
+
+impl<R: Read> Iterator for PacketStream<R> {
+    type Item = Result<Packet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+impl<R: Read> BitSource for HexReader<R> {
+    fn read_field(&mut self, _field: Field, count: usize) -> Result<u64> {
+        self.read_bits(count)
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+}
+
+/// Reads the bits of a hex-encoded transmission, one hex digit at a time.
+struct HexReader<R> {
+    bytes: Bytes<BufReader<R>>,
+    /// Bits that have been decoded but not consumed yet (only the lowest `buffered` bits are
+    /// meaningful).
+    buffer: u64,
+    buffered: usize,
+    /// Number of bits consumed since the start of the current transmission.
+    position: usize,
+    /// Whether we've reached the whitespace (or EOF) that ends the current transmission.
+    ended: bool,
+}
+
+impl<R: Read> HexReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            buffer: 0,
+            buffered: 0,
+            position: 0,
+            ended: true,
+        }
+    }
+
+    /// Skip to the beginning of the next transmission. Returns `false` if there are no more
+    /// transmissions in the input.
+    fn start_transmission(&mut self) -> Result<bool> {
+        // Drop what's left of a transmission that failed to decode.
+        while !self.ended {
+            self.next_byte()?;
+        }
+        self.buffer = 0;
+        self.buffered = 0;
+        self.position = 0;
+        self.ended = false;
+        loop {
+            match self.bytes.next().transpose()? {
+                None => {
+                    self.ended = true;
+                    return Ok(false);
+                }
+                Some(b) if b.is_ascii_whitespace() => continue,
+                Some(b) => {
+                    self.push_digit(b)?;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// Consume the rest of the current transmission, which must only contain zero bits. Returns
+    /// the number of padding bits.
+    fn skip_padding(&mut self) -> Result<usize> {
+        let mut padding = 0;
+        loop {
+            ensure!(self.buffer == 0, "Non-zero padding after packet");
+            padding += self.buffered;
+            self.position += self.buffered;
+            self.buffered = 0;
+            if !self.next_digit()? {
+                return Ok(padding);
+            }
+        }
+    }
+
+    /// Read the next `count` bits of the transmission as a big-endian number.
+    fn read_bits(&mut self, count: usize) -> Result<u64> {
+        ensure!(count <= 32, "Can't read more than 32 bits at once");
+        while self.buffered < count {
+            if !self.next_digit()? {
+                bail!("Unexpected end of transmission");
+            }
+        }
+        self.buffered -= count;
+        let value = self.buffer >> self.buffered;
+        self.buffer &= (1 << self.buffered) - 1;
+        self.position += count;
+
+        Ok(value)
+    }
+
+    /// Decode the next hex digit into the buffer. Returns `false` at the end of the transmission.
+    fn next_digit(&mut self) -> Result<bool> {
+        match self.next_byte()? {
+            Some(b) => {
+                self.push_digit(b)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Read the next byte of the current transmission, or `None` at its end.
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        if self.ended {
+            return Ok(None);
+        }
+        match self.bytes.next().transpose()? {
+            Some(b) if !b.is_ascii_whitespace() => Ok(Some(b)),
+            _ => {
+                self.ended = true;
+                Ok(None)
+            }
+        }
+    }
+
+    fn push_digit(&mut self, b: u8) -> Result<()> {
+        let digit = (b as char)
+            .to_digit(16)
+            .with_context(|| format!("Invalid hex digit {:?}", b as char))?;
+        self.buffer = (self.buffer << 4) | digit as u64;
+        self.buffered += 4;
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        panic,
+        sync::mpsc::{self, RecvTimeoutError},
+        thread,
+        time::Duration,
+    };
+
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
     fn test_literal() {
         let content = "D2FE28";
         let mut bv = parse_hex_data(content).unwrap();
-        let packet = parse_packet(&mut bv);
+        let packet = parse_packet(&mut bv).unwrap();
         assert_eq!(
             Packet {
                 header: Header::new(6, 4),
-                data: PacketData::Literal(2021)
+                data: PacketData::Literal(2021),
+                span: 0..21,
             },
             packet
         );
@@ -186,7 +861,7 @@ mod tests {
     fn test_operator1() {
         let content = "38006F45291200";
         let mut bv = parse_hex_data(content).unwrap();
-        let packet = parse_packet(&mut bv);
+        let packet = parse_packet(&mut bv).unwrap();
 
         dbg!(&packet);
         assert_eq!(Header::new(1, 6), packet.header);
@@ -197,10 +872,368 @@ mod tests {
     fn test_operator2() {
         let content = "EE00D40C823060";
         let mut bv = parse_hex_data(content).unwrap();
-        let packet = parse_packet(&mut bv);
+        let packet = parse_packet(&mut bv).unwrap();
 
         dbg!(&packet);
         assert_eq!(Header::new(7, 3), packet.header);
         assert!(matches!(packet.data, PacketData::Operator(packets) if packets.len() == 3));
     }
+
+    #[test]
+    fn test_stream() {
+        let content = "D2FE28\n38006F45291200\n\nEE00D40C823060\n";
+        let packets = PacketStream::new(content.as_bytes())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(3, packets.len());
+        assert_eq!(PacketData::Literal(2021), packets[0].data);
+        assert_eq!(Header::new(1, 6), packets[1].header);
+        assert_eq!(Header::new(7, 3), packets[2].header);
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let mut stream = PacketStream::new("D2FE".as_bytes());
+        assert!(stream.next().unwrap().is_err());
+
+        let mut stream = PacketStream::new("D2FE2X".as_bytes());
+        assert!(stream.next().unwrap().is_err());
+
+        let mut stream = PacketStream::new("D2FE29".as_bytes());
+        assert!(stream.next().unwrap().is_err());
+
+        // the rest of a bad transmission is skipped
+        let mut stream = PacketStream::new("D2FEZ8D2FE28 D2FE28".as_bytes());
+        assert!(stream.next().unwrap().is_err());
+        assert_eq!(
+            PacketData::Literal(2021),
+            stream.next().unwrap().unwrap().data
+        );
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut out = Vec::new();
+        dump("38006F45291200".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let expected = "\
+transmission 1
+     0  version         001  1
+     3  type id         110  6 (less than)
+     6  length type id  0  0 (length in bits)
+     7  total length    000000000011011  27
+    22    version         110  6
+    25    type id         100  4 (literal)
+    28    literal group   01010  10 (last)
+    33    version         010  2
+    36    type id         100  4 (literal)
+    39    literal group   10001  1
+    44    literal group   00100  4 (last)
+    49  padding         0000000
+3 packets (2 literals), depth 2, 49 bits
+
+";
+        assert_eq!(expected, out);
+
+        let mut out = Vec::new();
+        assert!(dump("D2FE".as_bytes(), &mut out).is_err());
+        assert!(String::from_utf8(out).unwrap().contains("literal group"));
+    }
+
+    #[test]
+    fn test_traversal() {
+        // 9C0141080250320F1802104A08 = 1 + 3 == 2 * 2
+        let mut bv = parse_hex_data("9C0141080250320F1802104A08").unwrap();
+        let packet = parse_packet(&mut bv).unwrap();
+
+        let pre = packet
+            .pre_order()
+            .map(|v| (v.packet.header.type_id, v.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(7, 0), (0, 1), (4, 2), (4, 2), (1, 1), (4, 2), (4, 2)],
+            pre
+        );
+
+        let post = packet
+            .post_order()
+            .map(|v| (v.packet.header.type_id, v.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(4, 2), (4, 2), (0, 1), (4, 2), (4, 2), (1, 1), (7, 0)],
+            post
+        );
+
+        assert_eq!(3, packet.depth());
+        assert_eq!(7, packet.node_count());
+        assert_eq!(4, packet.find_by_type(4).count());
+        assert_eq!(1, packet.eval().unwrap());
+
+        // children are laid out one after the other within their parent's span
+        for visit in packet.pre_order() {
+            let children = visit.packet.children();
+            if let (Some(first), Some(last)) = (children.first(), children.last()) {
+                assert!(visit.packet.span.start < first.span.start);
+                assert_eq!(visit.packet.span.end, last.span.end);
+                for pair in children.windows(2) {
+                    assert_eq!(pair[0].span.end, pair[1].span.start);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        let cases = [
+            ("C200B40A82", 3),
+            ("04005AC33890", 54),
+            ("880086C3E88112", 7),
+            ("CE00C43D881120", 9),
+            ("D8005AC2A8F0", 1),
+            ("F600BC2D8F", 0),
+            ("9C005AC2F8F0", 0),
+        ];
+        for (hex, expected) in cases {
+            let mut bv = parse_hex_data(hex).unwrap();
+            assert_eq!(
+                expected,
+                parse_packet(&mut bv).unwrap().eval().unwrap(),
+                "{}",
+                hex
+            );
+        }
+    }
+
+    fn lit(n: u64) -> Packet {
+        Packet {
+            header: Header::new(0, 4),
+            data: PacketData::Literal(n),
+            span: 0..0,
+        }
+    }
+
+    fn op(type_id: u8, packets: Vec<Packet>) -> Packet {
+        Packet {
+            header: Header::new(0, type_id),
+            data: PacketData::Operator(packets),
+            span: 0..0,
+        }
+    }
+
+    #[test]
+    fn test_simplify() {
+        // sum(sum(1, 0), product(1, 5), max(3)) => 9
+        let packet = op(
+            0,
+            vec![
+                op(0, vec![lit(1), lit(0)]),
+                op(1, vec![lit(1), lit(5)]),
+                op(3, vec![lit(3)]),
+            ],
+        );
+        assert_eq!(lit(9), packet.simplify());
+
+        // sum(1, gt(1, 2), 2) => sum(3, gt(1, 2))
+        let gt = || op(5, vec![lit(1), lit(2)]);
+        let packet = op(0, vec![lit(1), gt(), lit(2)]);
+        assert_eq!(op(0, vec![lit(3), gt()]), packet.simplify());
+        // min(3, 5, gt(1, 2)) => min(3, gt(1, 2)), product(2, 3, gt(1, 2)) => product(6, gt(1, 2))
+        let packet = op(2, vec![lit(3), lit(5), gt()]);
+        assert_eq!(op(2, vec![lit(3), gt()]), packet.simplify());
+        let packet = op(1, vec![lit(2), gt(), lit(3)]);
+        assert_eq!(op(1, vec![lit(6), gt()]), packet.simplify());
+
+        // literals are left alone if combining them overflows
+        let packet = op(0, vec![lit(u64::MAX), lit(1), gt()]);
+        assert_eq!(op(0, vec![lit(u64::MAX), lit(1), gt()]), packet.simplify());
+
+        // sum(1, 2)
+        let mut stream = PacketStream::new("C200B40A82".as_bytes());
+        let packet = stream.next().unwrap().unwrap().simplify();
+        assert_eq!(PacketData::Literal(3), packet.data);
+
+        // product(2, sum(0, 0)) => 0
+        let packet = op(1, vec![lit(2), op(0, vec![lit(0), lit(0)])]);
+        assert_eq!(lit(0), packet.simplify());
+
+        // min(max(2, max(0, 4)), 1) => 1
+        let packet = op(
+            2,
+            vec![op(3, vec![lit(2), op(3, vec![lit(0), lit(4)])]), lit(1)],
+        );
+        assert_eq!(lit(1), packet.simplify());
+
+        // comparisons keep both their operands
+        let packet = op(5, vec![op(0, vec![lit(2)]), lit(1)]);
+        assert_eq!(op(5, vec![lit(2), lit(1)]), packet.simplify());
+    }
+
+    #[test]
+    fn test_encode() {
+        let hexes = [
+            "D2FE28",
+            "38006F45291200",
+            "EE00D40C823060",
+            "8A004A801A8002F478",
+            "620080001611562C8802118E34",
+            "C0015000016115A2E0802F182340",
+            "A0016C880162017C3686B18A3D4780",
+            "9C0141080250320F1802104A08",
+        ];
+        for hex in hexes {
+            let mut bv = parse_hex_data(hex).unwrap();
+            let packet = parse_packet(&mut bv).unwrap();
+
+            let encoded = packet.to_hex().unwrap();
+            let mut bv = parse_hex_data(&encoded).unwrap();
+            let decoded = parse_packet(&mut bv).unwrap();
+            assert_eq!(packet.version_sum(), decoded.version_sum(), "{}", hex);
+            assert_eq!(packet.node_count(), decoded.node_count(), "{}", hex);
+            assert_eq!(encoded, decoded.to_hex().unwrap(), "{}", hex);
+
+            let eval = packet.eval().unwrap();
+            let simplified = packet.simplify().to_hex().unwrap();
+            let mut bv = parse_hex_data(&simplified).unwrap();
+            assert_eq!(
+                eval,
+                parse_packet(&mut bv).unwrap().eval().unwrap(),
+                "{}",
+                hex
+            );
+        }
+
+        let mut literal = lit(2021);
+        literal.header.version = 6;
+        assert_eq!("D2FE28", literal.to_hex().unwrap());
+    }
+
+    const FUZZ_SEEDS: [&str; 4] = [
+        "D2FE28",
+        "38006F45291200",
+        "A0016C880162017C3686B18A3D4780",
+        "9C0141080250320F1802104A08",
+    ];
+    const HEX_DIGITS: &[u8] = b"0123456789ABCDEF";
+
+    /// Hex strings, either random or made by mutating one of the puzzle examples so that they
+    /// get past the first few fields.
+    fn hex_input() -> impl Strategy<Value = String> {
+        let mutation = (any::<prop::sample::Index>(), 0..16usize, 0..5u8);
+        let mutated = (
+            prop::sample::select(&FUZZ_SEEDS[..]),
+            prop::collection::vec(mutation, 0..8),
+        )
+            .prop_map(|(seed, mutations)| {
+                let mut input = seed.as_bytes().to_vec();
+                for (index, digit, kind) in mutations {
+                    let i = index.index(input.len() + 1);
+                    match kind {
+                        0 if i < input.len() => input[i] = HEX_DIGITS[digit],
+                        1 => input.insert(i, HEX_DIGITS[digit]),
+                        2 if i < input.len() => {
+                            input.remove(i);
+                        }
+                        3 => input.truncate(i),
+                        _ => {
+                            let copy = input[i..].to_vec();
+                            input.extend(copy);
+                        }
+                    }
+                }
+                String::from_utf8(input).unwrap()
+            });
+
+        prop_oneof![1 => "[0-9A-F]{0,64}", 3 => mutated]
+    }
+
+    /// Run every consumer of decoded packets on `input`.
+    fn decode_everything(input: &str) {
+        for packet in PacketStream::new(input.as_bytes()).flatten() {
+            assert!(packet.depth() < MAX_DEPTH, "{}", input);
+            packet.version_sum();
+            packet.node_count();
+            let eval = packet.eval();
+            if let Ok(hex) = packet.to_hex() {
+                let decoded = PacketStream::new(hex.as_bytes()).next().unwrap().unwrap();
+                assert_eq!(packet.node_count(), decoded.node_count(), "{}", input);
+            }
+            let simplified = packet.simplify();
+            if let Ok(eval) = eval {
+                assert_eq!(eval, simplified.eval().unwrap(), "{}", input);
+            }
+        }
+        dump(input.as_bytes(), &mut io::sink()).ok();
+    }
+
+    /// Run `f` on another thread, failing if it doesn't return within a second.
+    fn with_timeout(input: String, f: fn(&str)) {
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            f(&input);
+            tx.send(()).ok();
+        });
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                if let Err(e) = handle.join() {
+                    panic::resume_unwind(e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => panic!("Decoding takes more than a second"),
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(5_000))]
+
+        #[test]
+        fn fuzz_decoder(input in hex_input()) {
+            with_timeout(input, decode_everything);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_crashers() {
+        // product of two 2^40 literals
+        let hex = "0600848C210842108400246108421084200";
+        let packet = PacketStream::new(hex.as_bytes()).next().unwrap().unwrap();
+        let err = packet.eval().unwrap_err();
+        assert!(err.to_string().contains("product at bit 0"), "{}", err);
+        decode_everything(hex);
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let mut bits = BitWriter::default();
+        for _ in 0..100_000 {
+            // sum with a single sub-packet
+            bits.write(0, 3);
+            bits.write(0, 3);
+            bits.write(1, 1);
+            bits.write(1, 11);
+        }
+        bits.write(0, 3);
+        bits.write(4, 3);
+        bits.write(1, 5);
+
+        let hex = bits.to_hex();
+        let mut stream = PacketStream::new(hex.as_bytes());
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("nested"), "{}", err);
+    }
+
+    #[test]
+    fn test_operand_count() {
+        // minimum without sub-packets used to make eval() panic
+        let mut stream = PacketStream::new("0A000".as_bytes());
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("without sub-packets"), "{}", err);
+
+        // less than with a single sub-packet
+        let mut stream = PacketStream::new("1A004408".as_bytes());
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("instead of 2"), "{}", err);
+    }
 }
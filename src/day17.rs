@@ -1,32 +1,328 @@
-use std::ops::RangeInclusive;
+use std::{collections::HashSet, fmt::Write, ops::RangeInclusive, str::FromStr};
 
-use anyhow::{Context, Result};
-use itertools::iproduct;
+use anyhow::{bail, ensure, Context, Result};
+use regex::Regex;
 
 pub fn run() -> Result<()> {
-    // let content = std::fs::read_to_string("inputs/day17.txt")?;
-    let mut probe = Probe::new(Vec2::new(0, 0), Vec2::new(0, 0), 144..=178, -100..=-76);
-
-    let mut max_y = i64::MIN;
-    let mut count = 0;
-    for (vx, vy) in iproduct!(1..200, -5000..20000) {
-        // println!("V=({}, {})   ", vx, vy);
-        probe.reset_with_velocity(Vec2::new(vx, vy));
-        if probe.hits_target() {
-            // println!("Target hit! V=({}, {}), max_y={}", vx, vy, probe.max_y);
-            count += 1;
-            if probe.max_y > max_y {
-                max_y = probe.max_y;
-            }
+    let content = std::fs::read_to_string("inputs/day17.txt")?;
+    let target = TargetArea::from_str(&content)?;
+
+    let trajectories = trajectories(&target, &Physics::default())?;
+    let max_y = trajectories
+        .iter()
+        .map(|t| t.apex)
+        .max()
+        .context("No velocity hits the target")?;
+    println!("day17 part1 = {}", max_y);
+    println!("day17 part2 = {}", trajectories.len());
+
+    Ok(())
+}
+
+/// Print the trajectory of a probe launched with the given velocity (as `x,y`), in the style of
+/// the puzzle description. Uses the target area from the puzzle input unless one is given.
+pub fn plot(velocity: &str, target: Option<&str>) -> Result<()> {
+    let velocity = Vec2::from_str(velocity)?;
+    let target = match target {
+        Some(target) => TargetArea::from_str(target)?,
+        None => TargetArea::from_str(&std::fs::read_to_string("inputs/day17.txt")?)?,
+    };
+
+    let mut probe = Probe::new(
+        Vec2::default(),
+        velocity,
+        target.x.clone(),
+        target.y.clone(),
+    );
+    let trajectory = probe.trace(velocity);
+    print!("{}", render(&trajectory, &target));
+    let Vec2 { x, y } = trajectory.velocity;
+    match trajectory.entry_step {
+        Some(step) => println!(
+            "{},{} hits the target at step {}, apex {}",
+            x, y, step, trajectory.apex
+        ),
+        None => println!("{},{} misses the target, apex {}", x, y, trajectory.apex),
+    }
+
+    Ok(())
+}
+
+/// Solve the puzzle input with different physics, given as `drag=N`, `gravity=N` and `wind=X,Y`
+/// settings.
+pub fn what_if(settings: &[String]) -> Result<()> {
+    let mut drag = 1;
+    let mut gravity = 1;
+    let mut wind = Vec2::default();
+    for setting in settings {
+        let (name, value) = setting
+            .split_once('=')
+            .context("Settings should be given as name=value")?;
+        match name {
+            "drag" => drag = value.parse().context("Invalid drag")?,
+            "gravity" => gravity = value.parse().context("Invalid gravity")?,
+            "wind" => wind = Vec2::from_str(value)?,
+            _ => bail!("Unknown setting {}", name),
         }
     }
-    println!("day17 part1 = {}", max_y);
-    println!("day17 part2 = {}", count);
+    let physics = Physics::new(drag, gravity, wind)?;
+
+    let content = std::fs::read_to_string("inputs/day17.txt")?;
+    let target = TargetArea::from_str(&content)?;
+    let trajectories = trajectories(&target, &physics)?;
+    match trajectories.iter().max_by_key(|t| t.apex) {
+        Some(highest) => {
+            let Vec2 { x, y } = highest.velocity;
+            println!(
+                "highest apex = {} (with velocity {},{})",
+                highest.apex, x, y
+            );
+        }
+        None => println!("No velocity hits the target"),
+    }
+    println!("hitting velocities = {}", trajectories.len());
 
     Ok(())
 }
 
-#[derive(Debug, Default, PartialEq, Eq)]
+/// Every trajectory that goes through the target area, sorted by initial velocity.
+fn trajectories(target: &TargetArea, physics: &Physics) -> Result<Vec<Trajectory>> {
+    let velocities = hitting_velocities(&target.x, &target.y, physics)?;
+    let mut probe = Probe::new(
+        Vec2::default(),
+        Vec2::default(),
+        target.x.clone(),
+        target.y.clone(),
+    )
+    .with_physics(*physics);
+
+    Ok(velocities.into_iter().map(|v| probe.trace(v)).collect())
+}
+
+/// Draw a trajectory and the target area, with `S` for the launch point, `#` for the positions
+/// of the probe and `T` for the target. Like in the puzzle description, the trajectory stops when
+/// the probe enters the target area.
+fn render(trajectory: &Trajectory, target: &TargetArea) -> String {
+    let shown =
+        &trajectory.positions[..trajectory.entry_step.unwrap_or(trajectory.positions.len())];
+    let xs = shown.iter().map(|p| p.x);
+    let ys = shown.iter().map(|p| p.y);
+    let bounds = [0, *target.x.start(), *target.x.end()];
+    let (x_min, x_max) = xs
+        .chain(bounds)
+        .fold((i64::MAX, i64::MIN), |(min, max), x| {
+            (min.min(x), max.max(x))
+        });
+    let bounds = [0, *target.y.start(), *target.y.end()];
+    let (y_min, y_max) = ys
+        .chain(bounds)
+        .fold((i64::MAX, i64::MIN), |(min, max), y| {
+            (min.min(y), max.max(y))
+        });
+
+    let positions = shown.iter().collect::<HashSet<_>>();
+    let mut out = String::new();
+    for y in (y_min..=y_max).rev() {
+        for x in x_min..=x_max {
+            let c = if x == 0 && y == 0 {
+                'S'
+            } else if positions.contains(&Vec2::new(x, y)) {
+                '#'
+            } else if target.x.contains(&x) && target.y.contains(&y) {
+                'T'
+            } else {
+                '.'
+            };
+            out.push(c);
+        }
+        writeln!(out).unwrap();
+    }
+
+    out
+}
+
+/// Find every initial velocity that puts the probe in the target area, without simulating all
+/// the candidate trajectories.
+///
+/// With a gravity of `g` and a vertical wind of `wy`, the height of the probe after `n` steps is
+/// `n * (vy + wy) - g * n * (n - 1) / 2`, so the vertical velocities that land in `target_y` at
+/// step `n` form a range. The same goes for the horizontal position as long as drag hasn't
+/// stopped the probe yet; once it has, the probe only moves with the wind.
+///
+/// Fails if the target can be hit with arbitrarily high velocities, which happens when it spans
+/// the launch height and the probe can stop moving within it.
+fn hitting_velocities(
+    target_x: &RangeInclusive<i64>,
+    target_y: &RangeInclusive<i64>,
+    physics: &Physics,
+) -> Result<Vec<Vec2>> {
+    let Physics {
+        drag,
+        gravity,
+        wind,
+    } = *physics;
+    let (x_min, x_max) = (*target_x.start(), *target_x.end());
+    let (y_min, y_max) = (*target_y.start(), *target_y.end());
+    let x_reach = x_min.abs().max(x_max.abs());
+    let y_reach = y_min.abs().max(y_max.abs());
+
+    // A probe shot upwards fast enough passes through y=0 again on its way down, so if it can stop
+    // within the target's x range, it will hit the target however fast it is shot.
+    if target_y.contains(&0) {
+        ensure!(
+            wind.x == 0,
+            "Target areas at the launch height are not supported with horizontal wind"
+        );
+        let stops_in_target = if drag == 0 {
+            target_x.contains(&0)
+        } else {
+            (0..)
+                .map(|vx| distance(vx, drag))
+                .take_while(|x| *x <= x_reach)
+                .any(|x| target_x.contains(&x) || target_x.contains(&-x))
+        };
+        ensure!(
+            !stops_in_target,
+            "Target area can be hit with arbitrarily high velocities"
+        );
+    }
+
+    // Faster probes skip over the target's y range on the way up and on the way down, and after
+    // `x_reach` steps, probes that are still moving horizontally have gone past it.
+    let max_steps = (2 * y_reach + 1).max(x_reach + 1);
+
+    let mut velocities = HashSet::new();
+    for n in 1..=max_steps {
+        let offset = gravity * n * (n - 1) / 2;
+        let vy_range = div_ceil(y_min + offset, n) - wind.y..=div_floor(y_max + offset, n) - wind.y;
+        if vy_range.is_empty() {
+            continue;
+        }
+        // where the probe needs to be, without taking the wind into account
+        let (x_min, x_max) = (x_min - n * wind.x, x_max - n * wind.x);
+        let forward = forward_velocities(n, x_min.max(1), x_max, drag);
+        let backward = forward_velocities(n, (-x_max).max(1), -x_min, drag).map(|vx| -vx);
+        let still = (x_min..=x_max).contains(&0).then_some(0);
+        for vx in forward.chain(backward).chain(still) {
+            velocities.extend(vy_range.clone().map(|vy| Vec2::new(vx, vy)));
+        }
+    }
+
+    let mut velocities = velocities.into_iter().collect::<Vec<_>>();
+    velocities.sort_unstable();
+    Ok(velocities)
+}
+
+/// Positive horizontal velocities that put the probe between `min` and `max` after `n` steps.
+fn forward_velocities(n: i64, min: i64, max: i64, drag: i64) -> impl Iterator<Item = i64> {
+    let offset = drag * n * (n - 1) / 2;
+    // probes that have stopped moving horizontally by step n...
+    let stopped = (1..=(drag * (n - 1)).min(max))
+        .filter(move |vx| (min..=max).contains(&distance(*vx, drag)));
+    // ...and those that are still moving
+    let moving = div_ceil(min + offset, n).max(drag * (n - 1) + 1)..=div_floor(max + offset, n);
+
+    stopped.chain(moving)
+}
+
+/// How far something goes with an initial velocity of `v`, slowing down by `deceleration` at each
+/// step until it stops.
+fn distance(v: i64, deceleration: i64) -> i64 {
+    if v <= 0 {
+        return 0;
+    }
+    let steps = div_ceil(v, deceleration);
+    steps * v - deceleration * steps * (steps - 1) / 2
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    a.div_euclid(b)
+}
+
+fn div_ceil(a: i64, b: i64) -> i64 {
+    -(-a).div_euclid(b)
+}
+
+/// The area the probe needs to go through, as described by the puzzle input.
+#[derive(Debug, PartialEq, Eq)]
+struct TargetArea {
+    x: RangeInclusive<i64>,
+    y: RangeInclusive<i64>,
+}
+
+impl FromStr for TargetArea {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = Regex::new(r"^target area: x=(-?\d+)\.\.(-?\d+), y=(-?\d+)\.\.(-?\d+)$")?;
+        let captures = pattern.captures(s.trim()).context("Invalid target area")?;
+        let bound = |i| {
+            captures
+                .get(i)
+                .context("Invalid target area")
+                .and_then(|m| m.as_str().parse::<i64>().context("Invalid number"))
+        };
+        let (x_min, x_max) = (bound(1)?, bound(2)?);
+        let (y_min, y_max) = (bound(3)?, bound(4)?);
+        ensure!(x_min <= x_max, "Invalid x range {}..{}", x_min, x_max);
+        ensure!(y_min <= y_max, "Invalid y range {}..{}", y_min, y_max);
+
+        Ok(TargetArea {
+            x: x_min..=x_max,
+            y: y_min..=y_max,
+        })
+    }
+}
+
+/// The path of a probe launched with a given velocity.
+#[derive(Debug)]
+struct Trajectory {
+    velocity: Vec2,
+    /// Position of the probe after each step, up to when it has got past the target area.
+    positions: Vec<Vec2>,
+    /// The step at which the probe first enters the target area, if it does (`positions` may go
+    /// on after that).
+    entry_step: Option<usize>,
+    /// The highest point of the whole trajectory, launch point included.
+    apex: i64,
+}
+
+/// The forces acting on the probe at each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Physics {
+    /// How much the horizontal velocity of the probe decreases towards 0.
+    drag: i64,
+    /// How much the vertical velocity of the probe decreases.
+    gravity: i64,
+    /// How much the probe drifts, on top of its own velocity.
+    wind: Vec2,
+}
+
+impl Physics {
+    fn new(drag: i64, gravity: i64, wind: Vec2) -> Result<Self> {
+        ensure!(drag >= 0, "Drag can't be negative");
+        // without gravity, probes could fly forever
+        ensure!(gravity > 0, "Gravity must be positive");
+
+        Ok(Self {
+            drag,
+            gravity,
+            wind,
+        })
+    }
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            drag: 1,
+            gravity: 1,
+            wind: Vec2::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct Vec2 {
     x: i64,
     y: i64,
@@ -38,12 +334,24 @@ impl Vec2 {
     }
 }
 
+impl FromStr for Vec2 {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s.split_once(',').context("Expected x,y")?;
+        let x = x.trim().parse::<i64>().context("Invalid number")?;
+        let y = y.trim().parse::<i64>().context("Invalid number")?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 struct Probe {
     pos: Vec2,
     velocity: Vec2,
     target_x: RangeInclusive<i64>,
     target_y: RangeInclusive<i64>,
+    physics: Physics,
     max_y: i64,
 }
 
@@ -59,46 +367,401 @@ impl Probe {
             velocity,
             target_x,
             target_y,
-            max_y: i64::MIN,
+            physics: Physics::default(),
+            max_y: pos.y,
         }
     }
 
+    fn with_physics(mut self, physics: Physics) -> Self {
+        self.physics = physics;
+        self
+    }
+
     fn reset_with_velocity(&mut self, velocity: Vec2) {
         self.pos = Vec2::default();
         self.velocity = velocity;
-        self.max_y = i64::MIN;
+        self.max_y = self.pos.y;
     }
 
     fn step(&mut self) {
-        self.pos.x += self.velocity.x;
-        self.pos.y += self.velocity.y;
+        self.pos.x += self.velocity.x + self.physics.wind.x;
+        self.pos.y += self.velocity.y + self.physics.wind.y;
         if self.pos.y > self.max_y {
             self.max_y = self.pos.y;
         }
 
         // drag
         if self.velocity.x > 0 {
-            self.velocity.x -= 1;
+            self.velocity.x = (self.velocity.x - self.physics.drag).max(0);
         } else if self.velocity.x < 0 {
-            self.velocity.x += 1;
+            self.velocity.x = (self.velocity.x + self.physics.drag).min(0);
         }
         // gravity
-        self.velocity.y -= 1;
+        self.velocity.y -= self.physics.gravity;
     }
 
+    #[cfg(test)]
     fn hits_target(&mut self) -> bool {
+        self.fly(|_| {})
+    }
+
+    /// Launch the probe with the given velocity, and record its trajectory until it has got past
+    /// the target area.
+    fn trace(&mut self, velocity: Vec2) -> Trajectory {
+        self.reset_with_velocity(velocity);
+        let mut positions = vec![];
+        let mut entry_step = None;
+        // `fly` stops at every step inside the target area
+        while self.fly(|pos| positions.push(*pos)) {
+            entry_step.get_or_insert(positions.len());
+        }
+
+        Trajectory {
+            velocity,
+            entry_step,
+            positions,
+            apex: self.max_y,
+        }
+    }
+
+    /// Move the probe until it either reaches the target area or can't reach it anymore, calling
+    /// `on_step` with each new position.
+    fn fly(&mut self, mut on_step: impl FnMut(&Vec2)) -> bool {
+        let wind = self.physics.wind;
         loop {
             self.step();
-            if (self.velocity.x > 0 && self.pos.x > *self.target_x.end())
-            || (self.velocity.x < 0 && self.pos.x < *self.target_x.start())
-            || (self.velocity.y < 0 && self.pos.y < *self.target_y.start()){
+            on_step(&self.pos);
+            // Drag brings the horizontal velocity of the probe closer to 0, so the speed it
+            // actually moves at goes from `velocity + wind` to `wind`.
+            let dx = self.velocity.x + wind.x;
+            let dy = self.velocity.y + wind.y;
+            if (dx >= 0 && wind.x >= 0 && self.pos.x > *self.target_x.end())
+                || (dx <= 0 && wind.x <= 0 && self.pos.x < *self.target_x.start())
+                || (dy < 0 && self.pos.y < *self.target_y.start())
+            {
                 // We've got past the target area: we will never hit it
                 return false;
             } else if self.target_x.contains(&self.pos.x) && self.target_y.contains(&self.pos.y) {
-                // we've hit the target area
+                // we've hit the target area, but may still be on our way up
+                let climb = distance(dy, self.physics.gravity);
+                self.max_y = self.max_y.max(self.pos.y + climb);
                 return true;
             }
             // otherwise, just keep going
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_probe() -> Probe {
+        Probe::new(Vec2::default(), Vec2::default(), 20..=30, -10..=-5)
+    }
+
+    #[test]
+    fn test_step() {
+        let mut probe = example_probe();
+        probe.reset_with_velocity(Vec2::new(7, 2));
+        let expected = [
+            (7, 2),
+            (13, 3),
+            (18, 3),
+            (22, 2),
+            (25, 0),
+            (27, -3),
+            (28, -7),
+        ];
+        for (x, y) in expected {
+            probe.step();
+            assert_eq!(Vec2::new(x, y), probe.pos);
+        }
+        assert_eq!(Vec2::new(0, -5), probe.velocity);
+        assert_eq!(3, probe.max_y);
+
+        // drag stops the probe, in either direction
+        for vx in [2, -2] {
+            probe.reset_with_velocity(Vec2::new(vx, 0));
+            probe.step();
+            probe.step();
+            probe.step();
+            assert_eq!(Vec2::new(3 * vx / 2, -3), probe.pos);
+            assert_eq!(Vec2::new(0, -3), probe.velocity);
+        }
+    }
+
+    #[test]
+    fn test_hits_target() {
+        let mut probe = example_probe();
+        for (vx, vy) in [(7, 2), (6, 3), (9, 0), (6, 9)] {
+            probe.reset_with_velocity(Vec2::new(vx, vy));
+            assert!(probe.hits_target(), "{},{}", vx, vy);
+        }
+        for (vx, vy) in [(17, -4), (6, 10), (0, 5), (-6, 3)] {
+            probe.reset_with_velocity(Vec2::new(vx, vy));
+            assert!(!probe.hits_target(), "{},{}", vx, vy);
+        }
+
+        probe.reset_with_velocity(Vec2::new(6, 9));
+        probe.hits_target();
+        assert_eq!(45, probe.max_y);
+    }
+
+    #[test]
+    fn test_example() {
+        let target = TargetArea::from_str("target area: x=20..30, y=-10..-5").unwrap();
+        let trajectories = trajectories(&target, &Physics::default()).unwrap();
+        let highest = trajectories.iter().max_by_key(|t| t.apex).unwrap();
+        assert_eq!(45, highest.apex);
+        // 6,9 and 7,9 both reach the highest point
+        assert_eq!(9, highest.velocity.y);
+        assert_eq!(112, trajectories.len());
+
+        // a few of the velocities listed in the puzzle
+        for (x, y) in [
+            (23, -10),
+            (25, -9),
+            (6, 0),
+            (7, -1),
+            (30, -5),
+            (8, -2),
+            (11, -4),
+        ] {
+            assert!(
+                trajectories.iter().any(|t| t.velocity == Vec2::new(x, y)),
+                "{},{}",
+                x,
+                y
+            );
+        }
+    }
+
+    /// Velocities hitting the target, found by simulating every probe that could possibly hit it.
+    fn brute_force(
+        target_x: &RangeInclusive<i64>,
+        target_y: &RangeInclusive<i64>,
+        physics: &Physics,
+    ) -> Vec<Vec2> {
+        let reach = [
+            *target_x.start(),
+            *target_x.end(),
+            *target_y.start(),
+            *target_y.end(),
+        ]
+        .iter()
+        .map(|n| n.abs())
+        .max()
+        .unwrap();
+        let x_reach = reach + 2 * reach * physics.wind.x.abs();
+        let y_reach = (physics.gravity + 1) * reach + physics.wind.y.abs();
+
+        let mut probe = Probe::new(
+            Vec2::default(),
+            Vec2::default(),
+            target_x.clone(),
+            target_y.clone(),
+        )
+        .with_physics(*physics);
+        let mut velocities = vec![];
+        for vx in -x_reach..=x_reach {
+            for vy in -y_reach - 1..=y_reach + 1 {
+                probe.reset_with_velocity(Vec2::new(vx, vy));
+                if probe.hits_target() {
+                    velocities.push(Vec2::new(vx, vy));
+                }
+            }
+        }
+        velocities
+    }
+
+    #[test]
+    fn test_hitting_velocities() {
+        let target_x = 20..=30;
+        let target_y = -10..=-5;
+        let velocities = hitting_velocities(&target_x, &target_y, &Physics::default()).unwrap();
+        assert_eq!(112, velocities.len());
+        assert_eq!(
+            brute_force(&target_x, &target_y, &Physics::default()),
+            velocities
+        );
+    }
+
+    #[test]
+    fn test_quadrants() {
+        let targets = [
+            (20..=30, -10..=-5),
+            (-30..=-20, -10..=-5),
+            (20..=30, 5..=10),
+            (-30..=-20, 5..=10),
+            (-5..=8, -10..=-5),
+            (-8..=5, 5..=10),
+            (4..=5, -3..=3),
+        ];
+        for (target_x, target_y) in targets {
+            let velocities = hitting_velocities(&target_x, &target_y, &Physics::default()).unwrap();
+            assert!(!velocities.is_empty(), "{:?} {:?}", target_x, target_y);
+            assert_eq!(
+                brute_force(&target_x, &target_y, &Physics::default()),
+                velocities,
+                "{:?} {:?}",
+                target_x,
+                target_y
+            );
+        }
+
+        // mirrored targets have mirrored solutions
+        let right = hitting_velocities(&(20..=30), &(5..=10), &Physics::default()).unwrap();
+        let left = hitting_velocities(&(-30..=-20), &(5..=10), &Physics::default()).unwrap();
+        assert_eq!(right.len(), left.len());
+        assert!(right.iter().all(|v| left.contains(&Vec2::new(-v.x, v.y))));
+
+        // the probe can stop within these targets, while at their height
+        assert!(hitting_velocities(&(20..=30), &(-5..=5), &Physics::default()).is_err());
+        assert!(hitting_velocities(&(-5..=5), &(0..=0), &Physics::default()).is_err());
+    }
+
+    #[test]
+    fn test_max_y() {
+        // the highest point is after the probe enters the target
+        let mut probe = Probe::new(Vec2::default(), Vec2::default(), 20..=30, 5..=10);
+        probe.reset_with_velocity(Vec2::new(20, 6));
+        assert!(probe.hits_target());
+        assert_eq!(21, probe.max_y);
+
+        // the launch point is the highest point when shooting down
+        probe.reset_with_velocity(Vec2::new(7, -1));
+        probe.target_y = -10..=-5;
+        assert!(probe.hits_target());
+        assert_eq!(0, probe.max_y);
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            TargetArea {
+                x: 20..=30,
+                y: -10..=-5
+            },
+            TargetArea::from_str("target area: x=20..30, y=-10..-5\n").unwrap()
+        );
+        assert_eq!(
+            TargetArea {
+                x: -30..=-20,
+                y: 5..=10
+            },
+            TargetArea::from_str("target area: x=-30..-20, y=5..10").unwrap()
+        );
+
+        assert!(TargetArea::from_str("target area: x=30..20, y=-10..-5").is_err());
+        assert!(TargetArea::from_str("target area: x=20..30, y=-5..-10").is_err());
+        assert!(TargetArea::from_str("target area: x=20..30").is_err());
+        assert!(TargetArea::from_str("target area: x=20..3a, y=-10..-5").is_err());
+        assert!(
+            TargetArea::from_str("target area: x=20..99999999999999999999, y=-10..-5").is_err()
+        );
+    }
+
+    #[test]
+    fn test_trajectories() {
+        let target = TargetArea::from_str("target area: x=20..30, y=-10..-5").unwrap();
+        let trajectories = trajectories(&target, &Physics::default()).unwrap();
+        assert_eq!(112, trajectories.len());
+        assert_eq!(45, trajectories.iter().map(|t| t.apex).max().unwrap());
+
+        let trajectory = trajectories
+            .iter()
+            .find(|t| t.velocity == Vec2::new(6, 9))
+            .unwrap();
+        assert_eq!(Some(20), trajectory.entry_step);
+        assert_eq!(Vec2::new(21, -10), trajectory.positions[19]);
+        // the probe goes on through the target area until it falls below it
+        assert_eq!(Vec2::new(21, -21), trajectory.positions[20]);
+        assert_eq!(21, trajectory.positions.len());
+    }
+
+    #[test]
+    fn test_render() {
+        let target = TargetArea::from_str("target area: x=20..30, y=-10..-5").unwrap();
+        let mut probe = Probe::new(
+            Vec2::default(),
+            Vec2::default(),
+            target.x.clone(),
+            target.y.clone(),
+        );
+
+        let trajectory = probe.trace(Vec2::new(7, 2));
+        assert_eq!(Some(7), trajectory.entry_step);
+        assert_eq!(Vec2::new(28, -12), *trajectory.positions.last().unwrap());
+        assert_eq!(3, trajectory.apex);
+        let expected = r".............#....#............
+.......#..............#........
+...............................
+S........................#.....
+...............................
+...............................
+...........................#...
+...............................
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTT#TT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+";
+        assert_eq!(expected, render(&trajectory, &target));
+
+        let trajectory = probe.trace(Vec2::new(17, -4));
+        assert_eq!(None, trajectory.entry_step);
+        let expected = r"S.................................
+..................................
+..................................
+..................................
+.................#................
+....................TTTTTTTTTTT...
+....................TTTTTTTTTTT...
+....................TTTTTTTTTTT...
+....................TTTTTTTTTTT...
+....................TTTTTTTTTTT..#
+....................TTTTTTTTTTT...
+";
+        assert_eq!(expected, render(&trajectory, &target));
+    }
+
+    #[test]
+    fn test_physics() {
+        let physics = [
+            Physics::new(0, 1, Vec2::default()).unwrap(),
+            Physics::new(2, 1, Vec2::default()).unwrap(),
+            Physics::new(1, 2, Vec2::default()).unwrap(),
+            Physics::new(3, 3, Vec2::default()).unwrap(),
+            Physics::new(1, 1, Vec2::new(1, 0)).unwrap(),
+            Physics::new(1, 1, Vec2::new(-2, 1)).unwrap(),
+            Physics::new(0, 2, Vec2::new(3, -1)).unwrap(),
+        ];
+        let targets = [(20..=30, -10..=-5), (-30..=-20, 5..=10), (-5..=8, -10..=-5)];
+        for physics in physics {
+            for (target_x, target_y) in targets.clone() {
+                let velocities = hitting_velocities(&target_x, &target_y, &physics).unwrap();
+                assert!(!velocities.is_empty());
+                assert_eq!(
+                    brute_force(&target_x, &target_y, &physics),
+                    velocities,
+                    "{:?} {:?} {:?}",
+                    physics,
+                    target_x,
+                    target_y
+                );
+            }
+        }
+
+        // without drag, the probe never stops unless it's shot straight up
+        let physics = Physics::new(0, 1, Vec2::default()).unwrap();
+        let velocities = hitting_velocities(&(20..=30), &(-5..=5), &physics).unwrap();
+        assert_eq!(brute_force(&(20..=30), &(-5..=5), &physics), velocities);
+        assert!(hitting_velocities(&(-5..=5), &(-5..=5), &physics).is_err());
+
+        assert!(Physics::new(-1, 1, Vec2::default()).is_err());
+        assert!(Physics::new(1, 0, Vec2::default()).is_err());
+    }
+}
@@ -32,7 +32,7 @@ struct Floor {
 impl Floor {
     fn new(width: usize, height: usize) -> Self {
         Self {
-            grid: iter::repeat(0).take(width * height).collect(),
+            grid: iter::repeat_n(0, width * height).collect(),
             width,
             height,
         }
@@ -115,9 +115,9 @@ impl Line {
 struct Pos(usize, usize);
 
 fn parse_data(content: &str) -> Result<Vec<Line>> {
+    let re = regex::Regex::new(r"^(\d+),(\d+) -> (\d+),(\d+)$")?;
     let mut positions = vec![];
     for line in content.lines() {
-        let re = regex::Regex::new(r"^(\d+),(\d+) -> (\d+),(\d+)$")?;
         let cap = re.captures(line).context("Failed to parse line")?;
         let x1 = get_capture(&cap, 1)?;
         let y1 = get_capture(&cap, 2)?;
@@ -5,11 +5,7 @@ struct Crabs(Vec<u64>);
 impl Crabs {
     /// Returns the cost of aligning all the crabs to the given horizontal position
     fn align_to(&self, pos: u64) -> u64 {
-        self.0
-            .iter()
-            .copied()
-            .map(|p| if p > pos { p - pos } else { pos - p })
-            .sum()
+        self.0.iter().copied().map(|p| p.abs_diff(pos)).sum()
     }
 
     /// Returns the cost of aligning all the crabs to the given horizontal position
@@ -18,7 +14,7 @@ impl Crabs {
             .iter()
             .copied()
             .map(|p| {
-                let diff = if p > pos { p - pos } else { pos - p };
+                let diff = p.abs_diff(pos);
                 if diff > 0 {
                     (diff * (diff + 1)) / 2
                 } else {
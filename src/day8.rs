@@ -168,12 +168,10 @@ impl FromStr for Entry {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let (patterns, digits) = s.split_once(" | ").context("bad input")?;
         let pattern_digits = patterns
-            .trim()
             .split_whitespace()
             .map(Digit::from_str)
             .collect::<Result<Vec<_>>>()?;
         let display_digits = digits
-            .trim()
             .split_whitespace()
             .map(Digit::from_str)
             .collect::<Result<Vec<_>>>()?;
@@ -125,7 +125,7 @@ impl Heightmap {
             if !basin.contains(&to_check) && self.grid[to_check] != 9 {
                 basin.insert(to_check);
                 let (cx, cy) = self.idx2pos(to_check);
-                indices_to_check.extend(self.get_neighbours_idx(cx, cy).into_iter());
+                indices_to_check.extend(self.get_neighbours_idx(cx, cy));
             }
         }
 
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
 mod day1;
 mod day2;
@@ -19,6 +19,30 @@ mod day16;
 mod day17;
 
 fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        None => run_all(),
+        Some("day10-check") => day10::check(&args[1..]),
+        Some("day12-paths") => day12::list_paths(&args[1..]),
+        Some("day12-dot") => day12::export_dot(&args[1..]),
+        Some("day13-folds") => day13::history(&args[1..]),
+        Some("day13-generate") => day13::generate(
+            args.get(1).context("Missing message")?,
+            args.get(2).context("Missing number of folds")?,
+            args.get(3).map(String::as_str),
+        ),
+        Some("day16-inspect") => day16::inspect(args.get(1).map(String::as_str)),
+        Some("day16-simplify") => day16::simplify(args.get(1).map(String::as_str)),
+        Some("day17-plot") => day17::plot(
+            args.get(1).context("Missing velocity")?,
+            args.get(2).map(String::as_str),
+        ),
+        Some("day17-physics") => day17::what_if(&args[1..]),
+        Some(command) => bail!("Unknown command: {}", command),
+    }
+}
+
+fn run_all() -> Result<()> {
     day1::run()?;
     day2::run()?;
     day3::run()?;